@@ -0,0 +1,320 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Serves a table of static assets, typically baked into flash with `include_bytes!`.
+// Assets are declared in a `static` so that their content type and ETag are computed at
+// build time:
+//
+//   static ASSETS: [Asset; 2] = [
+//       Asset::new("/index.html", include_bytes!("ui/index.html"))
+//           .with_gzip(include_bytes!("ui/index.html.gz")),
+//       Asset::new("/app.js", include_bytes!("ui/app.js")),
+//   ];
+
+use router::{Params, RouteHandler};
+use server::{Handler, Request, ResponseWriter};
+use traits::Channel;
+use {HttpError, HttpHeader, HttpMethod};
+
+static CONTENT_TYPES: [(&'static str, &'static str); 13] = [
+    (".html", "text/html; charset=utf-8"),
+    (".htm", "text/html; charset=utf-8"),
+    (".css", "text/css; charset=utf-8"),
+    (".js", "text/javascript; charset=utf-8"),
+    (".json", "application/json"),
+    (".txt", "text/plain; charset=utf-8"),
+    (".svg", "image/svg+xml"),
+    (".png", "image/png"),
+    (".jpg", "image/jpeg"),
+    (".ico", "image/x-icon"),
+    (".wasm", "application/wasm"),
+    (".woff2", "font/woff2"),
+    (".gz", "application/gzip"),
+];
+
+const fn ends_with(value: &[u8], suffix: &[u8]) -> bool {
+    if suffix.len() > value.len() {
+        return false;
+    }
+    let offset = value.len() - suffix.len();
+    let mut i = 0;
+    while i < suffix.len() {
+        if value[offset + i] != suffix[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+// The content type for a path, based on its extension.
+pub const fn content_type(path: &str) -> &'static str {
+    let mut i = 0;
+    while i < CONTENT_TYPES.len() {
+        if ends_with(path.as_bytes(), CONTENT_TYPES[i].0.as_bytes()) {
+            return CONTENT_TYPES[i].1;
+        }
+        i += 1;
+    }
+    "application/octet-stream"
+}
+
+// 64 bits FNV-1a hash, good enough to tell versions of an asset apart.
+pub const fn hash(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    let mut i = 0;
+    while i < data.len() {
+        hash ^= data[i] as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    hash
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Asset {
+    pub path: &'static str,
+    pub content_type: &'static str,
+    pub data: &'static [u8],
+    pub gzip: Option<&'static [u8]>,
+    // Each variant is a different representation, with its own strong ETag.
+    etag: u64,
+    gzip_etag: u64,
+}
+
+impl Asset {
+    pub const fn new(path: &'static str, data: &'static [u8]) -> Asset {
+        Asset {
+            path: path,
+            content_type: content_type(path),
+            data: data,
+            gzip: None,
+            etag: hash(data),
+            gzip_etag: 0,
+        }
+    }
+
+    // Adds a precompressed variant of the data, served to clients accepting gzip.
+    pub const fn with_gzip(self, gzip: &'static [u8]) -> Asset {
+        Asset {
+            gzip: Some(gzip),
+            gzip_etag: hash(gzip),
+            ..self
+        }
+    }
+
+    pub const fn with_content_type(self, content_type: &'static str) -> Asset {
+        Asset { content_type: content_type, ..self }
+    }
+}
+
+// Whether an Accept-Encoding header value allows gzip. An explicit gzip entry takes
+// precedence over "*".
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    let mut wildcard = None;
+    for coding in accept_encoding.split(',') {
+        let mut params = coding.split(';');
+        let name = params.next().unwrap_or("").trim();
+        // A zero quality value means "not acceptable".
+        let acceptable = !params.any(|param| {
+            let param = param.trim();
+            param.starts_with("q=0") &&
+            param[3..].trim_start_matches('.').bytes().all(|value| value == b'0')
+        });
+        if name.eq_ignore_ascii_case("gzip") {
+            return acceptable;
+        } else if name == "*" {
+            wildcard = Some(acceptable);
+        }
+    }
+    wildcard.unwrap_or(false)
+}
+
+// Whether an If-None-Match header value matches the ETag. The comparison is weak, as
+// required for this header.
+fn matches_etag(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').any(|tag| {
+        let tag = tag.trim();
+        tag == "*" || tag.trim_start_matches("W/") == etag
+    })
+}
+
+pub struct Assets<'a> {
+    assets: &'a [Asset],
+    index: &'a str,
+}
+
+impl<'a> Assets<'a> {
+    pub fn new(assets: &'a [Asset]) -> Self {
+        Assets {
+            assets: assets,
+            index: "index.html",
+        }
+    }
+
+    // The file served for paths ending with a `/`.
+    pub fn set_index(&mut self, index: &'a str) {
+        self.index = index;
+    }
+
+    fn find(&self, path: &str) -> Option<&'a Asset> {
+        let assets = self.assets;
+        if path.ends_with('/') {
+            assets.iter().find(|asset| {
+                asset.path.len() == path.len() + self.index.len() &&
+                asset.path.starts_with(path) && asset.path.ends_with(self.index)
+            })
+        } else {
+            assets.iter().find(|asset| asset.path == path)
+        }
+    }
+}
+
+impl<'a, T: Channel> Handler<T> for Assets<'a> {
+    fn handle(&mut self,
+              request: &Request,
+              response: &mut ResponseWriter<T>)
+              -> Result<(), HttpError> {
+        let asset = match self.find(request.path()) {
+            Some(asset) => asset,
+            None => {
+                response.status(404, "Not Found")?.send(&[])?;
+                return Ok(());
+            }
+        };
+
+        if request.method != HttpMethod::Get && request.method != HttpMethod::Head {
+            response.status(405, "Method Not Allowed")?
+                .header(HttpHeader::Allow, "GET, HEAD")?
+                .send(&[])?;
+            return Ok(());
+        }
+
+        let gzip = asset.gzip.filter(|_| {
+            request.header(&HttpHeader::AcceptEncoding).map(accepts_gzip).unwrap_or(false)
+        });
+        let (data, etag) = match gzip {
+            Some(gzip) => (gzip, format!("\"{:016x}\"", asset.gzip_etag)),
+            None => (asset.data, format!("\"{:016x}\"", asset.etag)),
+        };
+
+        let not_modified = request.header(&HttpHeader::IfNoneMatch)
+            .map(|value| matches_etag(value, &etag))
+            .unwrap_or(false);
+        if not_modified {
+            response.status(304, "Not Modified")?;
+        } else {
+            response.status(200, "OK")?
+                .header(HttpHeader::ContentType, asset.content_type)?;
+            if gzip.is_some() {
+                response.header(HttpHeader::ContentEncoding, "gzip")?;
+            }
+        }
+
+        response.header(HttpHeader::Etag, &etag)?;
+        // Caches have to know that the response depends on the Accept-Encoding header.
+        if asset.gzip.is_some() {
+            response.header(HttpHeader::Vary, "Accept-Encoding")?;
+        }
+
+        if not_modified {
+            response.body(&[])?;
+        } else {
+            response.send(data)?;
+        }
+        Ok(())
+    }
+}
+
+// For use behind a wildcard route, the full request path is looked up.
+impl<'a, T: Channel> RouteHandler<T> for Assets<'a> {
+    fn handle(&mut self,
+              request: &Request,
+              _: &Params,
+              response: &mut ResponseWriter<T>)
+              -> Result<(), HttpError> {
+        Handler::handle(self, request, response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use collections::String;
+    use core::str;
+    use server::Server;
+    use traits::MemoryChannel;
+
+    static ASSETS: [Asset; 3] = [Asset::new("/index.html", b"<h1>Hi</h1>").with_gzip(b"GZIP"),
+                                 Asset::new("/app.js", b"run()"),
+                                 Asset::new("/data.bin", b"\x00\x01")
+                                     .with_content_type("application/x-firmware")];
+
+    fn serve(request: &'static str) -> String {
+        let mut channel = MemoryChannel::new(request.as_bytes());
+        Server::new().serve(&mut channel, &mut Assets::new(&ASSETS)).unwrap();
+        String::from(str::from_utf8(channel.sent()).unwrap())
+    }
+
+    #[test]
+    fn test_build_time_values() {
+        const HASH: u64 = hash(b"a");
+        assert_eq!(HASH, 0xaf63dc4c8601ec8c);
+        assert_eq!(ASSETS[0].content_type, "text/html; charset=utf-8");
+        assert_eq!(ASSETS[1].content_type, "text/javascript; charset=utf-8");
+        assert_eq!(ASSETS[2].content_type, "application/x-firmware");
+        assert_eq!(content_type("/firmware"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_accept_encoding() {
+        assert!(accepts_gzip("gzip"));
+        assert!(accepts_gzip("deflate, gzip;q=1.0, br"));
+        assert!(accepts_gzip("*"));
+        assert!(!accepts_gzip("deflate, br"));
+        assert!(!accepts_gzip("gzip;q=0"));
+        assert!(!accepts_gzip("br, gzip; q=0.000"));
+        assert!(!accepts_gzip("gzip;q=0, *"));
+        assert!(!accepts_gzip("*, gzip;q=0"));
+        assert!(accepts_gzip("*;q=0, gzip"));
+    }
+
+    #[test]
+    fn test_assets() {
+        let etag = format!("\"{:016x}\"", hash(b"run()"));
+        assert_eq!(serve("GET /app.js HTTP/1.1\r\n\r\n"),
+                   format!("HTTP/1.1 200 OK\r\nContent-Type: text/javascript; \
+                            charset=utf-8\r\nETag: {}\r\nContent-Length: 5\r\n\r\nrun()",
+                           etag));
+        assert_eq!(serve("HEAD /data.bin HTTP/1.1\r\n\r\n"),
+                   format!("HTTP/1.1 200 OK\r\nContent-Type: application/x-firmware\r\nETag: \
+                            \"{:016x}\"\r\nContent-Length: 2\r\n\r\n",
+                           hash(b"\x00\x01")));
+
+        let request = format!("GET /app.js HTTP/1.1\r\nIf-None-Match: \"x\", W/{}\r\n\r\n", etag);
+        let mut channel = MemoryChannel::new(request.as_bytes());
+        Server::new().serve(&mut channel, &mut Assets::new(&ASSETS)).unwrap();
+        assert_eq!(str::from_utf8(channel.sent()).unwrap(),
+                   format!("HTTP/1.1 304 Not Modified\r\nETag: {}\r\n\r\n", etag));
+
+        assert!(serve("GET /missing.js HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404 "));
+        assert!(serve("POST /app.js HTTP/1.1\r\n\r\n")
+            .starts_with("HTTP/1.1 405 Method Not Allowed\r\nAllow: GET, HEAD\r\n"));
+    }
+
+    #[test]
+    fn test_assets_gzip() {
+        let response = serve("GET / HTTP/1.1\r\nAccept-Encoding: gzip, deflate\r\n\r\n");
+        assert_eq!(response,
+                   format!("HTTP/1.1 200 OK\r\nContent-Type: text/html; \
+                            charset=utf-8\r\nContent-Encoding: gzip\r\nETag: \
+                            \"{:016x}\"\r\nVary: Accept-Encoding\r\nContent-Length: \
+                            4\r\n\r\nGZIP",
+                           hash(b"GZIP")));
+
+        let response = serve("GET /index.html HTTP/1.1\r\nAccept-Encoding: identity\r\n\r\n");
+        assert!(response.contains(&format!("ETag: \"{:016x}\"\r\n", hash(b"<h1>Hi</h1>"))));
+        assert!(response.ends_with("Vary: Accept-Encoding\r\nContent-Length: 11\r\n\r\n<h1>Hi</h1>"));
+    }
+}
@@ -0,0 +1,703 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// An asynchronous flavor of `Channel` and `Client`.
+// Only `core::future` is used, so any executor can drive these futures.
+
+use collections::{String, Vec};
+use core::future::Future;
+use core::ops::Fn;
+use core::pin::Pin;
+use core::str;
+use core::task::{Context, Poll};
+
+use traits::{Channel, ChannelError};
+use url;
+use {ClientState, HttpError, HttpHeader, HttpMethod, Response, HTTP_VERSION, LINE_END};
+use {parse_header_line, parse_status_line};
+
+// Same limit as the blocking client for status and header lines.
+const MAX_LINE_LENGTH: usize = 256;
+
+pub trait AsyncChannel {
+    // Opens a channel to the given host:port destination, with TLS support if needed.
+    // The same arguments are passed again on each poll until the channel is opened.
+    fn poll_open(&mut self,
+                 cx: &mut Context,
+                 host: &str,
+                 port: u16,
+                 tls: bool)
+                 -> Poll<Result<(), ChannelError>>;
+
+    // Tries to send the data.
+    // Returns the number of bytes successfully sent, or an error.
+    fn poll_send(&mut self, cx: &mut Context, data: &[u8]) -> Poll<Result<usize, ChannelError>>;
+
+    // Tries to receive at most `data.len()` bytes.
+    // Returns the number of bytes successfully received, or an error.
+    fn poll_recv(&mut self,
+                 cx: &mut Context,
+                 data: &mut [u8])
+                 -> Poll<Result<usize, ChannelError>>;
+
+    // Gracefully closes the channel. Does nothing by default.
+    fn poll_close(&mut self, _cx: &mut Context) -> Poll<Result<(), ChannelError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn open<'a>(&'a mut self, host: &'a str, port: u16, tls: bool) -> OpenFuture<'a, Self>
+        where Self: Sized
+    {
+        OpenFuture {
+            channel: self,
+            host: host,
+            port: port,
+            tls: tls,
+        }
+    }
+
+    fn send<'a>(&'a mut self, data: &'a [u8]) -> SendFuture<'a, Self>
+        where Self: Sized
+    {
+        SendFuture {
+            channel: self,
+            data: data,
+        }
+    }
+
+    // Keeps sending until all the data is sent.
+    fn send_all<'a>(&'a mut self, data: &'a [u8]) -> SendAllFuture<'a, Self>
+        where Self: Sized
+    {
+        SendAllFuture {
+            channel: self,
+            data: data,
+            pos: 0,
+        }
+    }
+
+    fn recv<'a>(&'a mut self, data: &'a mut [u8]) -> RecvFuture<'a, Self>
+        where Self: Sized
+    {
+        RecvFuture {
+            channel: self,
+            data: data,
+        }
+    }
+
+    fn close<'a>(&'a mut self) -> CloseFuture<'a, Self>
+        where Self: Sized
+    {
+        CloseFuture { channel: self }
+    }
+}
+
+pub struct OpenFuture<'a, C: 'a> {
+    channel: &'a mut C,
+    host: &'a str,
+    port: u16,
+    tls: bool,
+}
+
+impl<'a, C: AsyncChannel> Future for OpenFuture<'a, C> {
+    type Output = Result<(), ChannelError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        this.channel.poll_open(cx, this.host, this.port, this.tls)
+    }
+}
+
+pub struct SendFuture<'a, C: 'a> {
+    channel: &'a mut C,
+    data: &'a [u8],
+}
+
+impl<'a, C: AsyncChannel> Future for SendFuture<'a, C> {
+    type Output = Result<usize, ChannelError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        this.channel.poll_send(cx, this.data)
+    }
+}
+
+pub struct SendAllFuture<'a, C: 'a> {
+    channel: &'a mut C,
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a, C: AsyncChannel> Future for SendAllFuture<'a, C> {
+    type Output = Result<(), ChannelError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        poll_send_all(this.channel, cx, this.data, &mut this.pos)
+    }
+}
+
+pub struct RecvFuture<'a, C: 'a> {
+    channel: &'a mut C,
+    data: &'a mut [u8],
+}
+
+impl<'a, C: AsyncChannel> Future for RecvFuture<'a, C> {
+    type Output = Result<usize, ChannelError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        this.channel.poll_recv(cx, this.data)
+    }
+}
+
+pub struct CloseFuture<'a, C: 'a> {
+    channel: &'a mut C,
+}
+
+impl<'a, C: AsyncChannel> Future for CloseFuture<'a, C> {
+    type Output = Result<(), ChannelError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.channel.poll_close(cx)
+    }
+}
+
+// Sends `data` starting at `pos`, keeping track of the progress across polls.
+fn poll_send_all<C: AsyncChannel>(channel: &mut C,
+                                  cx: &mut Context,
+                                  data: &[u8],
+                                  pos: &mut usize)
+                                  -> Poll<Result<(), ChannelError>> {
+    while *pos < data.len() {
+        match channel.poll_send(cx, &data[*pos..]) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Ready(Ok(0)) => return Poll::Ready(Err(ChannelError::EndOfStream)),
+            Poll::Ready(Ok(sent)) => *pos += sent,
+        }
+    }
+    Poll::Ready(Ok(()))
+}
+
+/// Exposes a blocking channel as an `AsyncChannel` whose operations are always ready.
+#[derive(Clone)]
+pub struct BlockingChannel<T> {
+    inner: T,
+}
+
+impl<T> BlockingChannel<T> {
+    pub fn new(inner: T) -> Self {
+        BlockingChannel { inner: inner }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Channel> AsyncChannel for BlockingChannel<T> {
+    fn poll_open(&mut self,
+                 _: &mut Context,
+                 host: &str,
+                 port: u16,
+                 tls: bool)
+                 -> Poll<Result<(), ChannelError>> {
+        Poll::Ready(self.inner.open(host, port, tls))
+    }
+
+    fn poll_send(&mut self, _: &mut Context, data: &[u8]) -> Poll<Result<usize, ChannelError>> {
+        Poll::Ready(self.inner.send(data, data.len()))
+    }
+
+    fn poll_recv(&mut self,
+                 _: &mut Context,
+                 data: &mut [u8])
+                 -> Poll<Result<usize, ChannelError>> {
+        let max_len = data.len();
+        Poll::Ready(self.inner.recv(data, max_len))
+    }
+}
+
+pub struct AsyncClient<'a, T> {
+    channel: T,
+    state: ClientState,
+    method: HttpMethod,
+    url: &'a str,
+    // The request line and headers are buffered until the first body send.
+    head: Vec<u8>,
+    headers_flushed: bool,
+}
+
+macro_rules! async_http_method {
+    ($method:ident, $enumv:ident) => (
+        pub fn $method(&mut self, url: &'a str) -> &mut Self {
+            self.request(HttpMethod::$enumv, url)
+        }
+    )
+}
+
+impl<'a, T> AsyncClient<'a, T> {
+    pub fn new(channel: T) -> Self
+        where T: AsyncChannel
+    {
+        AsyncClient {
+            channel: channel,
+            state: ClientState::Error,
+            method: HttpMethod::Get,
+            url: "",
+            head: Vec::new(),
+            headers_flushed: false,
+        }
+    }
+
+    pub fn open<'c>(&'c mut self) -> OpenRequest<'c, 'a, T>
+        where T: AsyncChannel
+    {
+        assert_eq!(self.state, ClientState::Created);
+
+        self.state = ClientState::Error;
+
+        // Get the host + port + secure state of the url, the transport layer is opened when
+        // the returned future is polled.
+        let target = match url::parse_url(self.url) {
+            Ok((scheme, _, _, _)) if scheme != "http" && scheme != "https" => {
+                Err(HttpError::UnsupportedScheme)
+            }
+            Ok((scheme, host, port, path)) => Ok((host, port, scheme == "https", path)),
+            Err(err) => Err(HttpError::from(err)),
+        };
+
+        OpenRequest {
+            client: Some(self),
+            target: Some(target),
+        }
+    }
+
+    pub fn headers(&mut self, headers: &[(HttpHeader, &str)]) -> Result<&mut Self, HttpError>
+        where T: AsyncChannel
+    {
+        assert_eq!(self.state, ClientState::HeadersOrBody);
+
+        for header in headers {
+            self.head.extend_from_slice(header.0.as_string().as_bytes());
+            self.head.extend_from_slice(header.1.as_bytes());
+            self.head.extend_from_slice(LINE_END.as_bytes());
+        }
+
+        Ok(self)
+    }
+
+    pub fn header(&mut self, name: HttpHeader, value: &str) -> Result<&mut Self, HttpError>
+        where T: AsyncChannel
+    {
+        self.headers(&[(name, value)])
+    }
+
+    fn _send<'c, 'b>(&'c mut self,
+                     body: &'b [u8],
+                     final_state: ClientState)
+                     -> SendRequest<'c, 'a, 'b, T>
+        where T: AsyncChannel
+    {
+        assert_eq!(self.state, ClientState::HeadersOrBody);
+
+        self.state = ClientState::Error;
+        self.finish_head();
+
+        SendRequest {
+            client: Some(self),
+            body: body,
+            head_pos: 0,
+            body_pos: 0,
+            final_state: final_state,
+        }
+    }
+
+    // Sends a part of the body. Can be called multiple times before a send()
+    pub fn body<'c, 'b>(&'c mut self, body: &'b [u8]) -> SendRequest<'c, 'a, 'b, T>
+        where T: AsyncChannel
+    {
+        self._send(body, ClientState::HeadersOrBody)
+    }
+
+    // Last or single send of a sequence.
+    pub fn send<'c, 'b>(&'c mut self, body: &'b [u8]) -> SendRequest<'c, 'a, 'b, T>
+        where T: AsyncChannel
+    {
+        self._send(body, ClientState::ReadResponse)
+    }
+
+    pub fn response<'c, F>(&'c mut self, filter: F) -> ResponseFuture<'c, 'a, T, F>
+        where T: AsyncChannel,
+              F: Fn(HttpHeader) -> bool
+    {
+        // Some methods don't need a body, so if we are in HeadersOrBody state the remaining
+        // part of the head is flushed by the returned future.
+        if self.state == ClientState::HeadersOrBody {
+            self.finish_head();
+            self.state = ClientState::ReadResponse;
+        }
+
+        assert_eq!(self.state, ClientState::ReadResponse);
+        self.state = ClientState::Error;
+
+        ResponseFuture {
+            client: Some(self),
+            filter: filter,
+            head_pos: 0,
+            line: Vec::new(),
+            status: None,
+            headers: Vec::new(),
+        }
+    }
+
+    // Adds the empty line after the headers, once.
+    fn finish_head(&mut self) {
+        if !self.headers_flushed {
+            self.headers_flushed = true;
+            self.head.extend_from_slice(LINE_END.as_bytes());
+        }
+    }
+
+    fn request(&mut self, method: HttpMethod, url: &'a str) -> &mut Self {
+        self.url = url;
+        self.method = method;
+        self.state = ClientState::Created;
+        self.head.clear();
+        self.headers_flushed = false;
+        self
+    }
+
+    async_http_method!(get, Get);
+    async_http_method!(head, Head);
+    async_http_method!(post, Post);
+    async_http_method!(put, Put);
+    async_http_method!(delete, Delete);
+}
+
+// (host, port, tls, path) of a request.
+type Target<'a> = (&'a str, u16, bool, &'a str);
+
+pub struct OpenRequest<'c, 'a: 'c, T: 'c> {
+    client: Option<&'c mut AsyncClient<'a, T>>,
+    // The request target, or the url parsing error.
+    target: Option<Result<Target<'a>, HttpError>>,
+}
+
+impl<'c, 'a, T: AsyncChannel> Future for OpenRequest<'c, 'a, T> {
+    type Output = Result<&'c mut AsyncClient<'a, T>, HttpError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let (host, port, tls, path) = match this.target.take().expect("polled after completion") {
+            Ok(target) => target,
+            Err(err) => return Poll::Ready(Err(err)),
+        };
+
+        let client = this.client.take().expect("polled after completion");
+        match client.channel.poll_open(cx, host, port, tls) {
+            Poll::Pending => {
+                this.target = Some(Ok((host, port, tls, path)));
+                this.client = Some(client);
+                return Poll::Pending;
+            }
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(HttpError::from(err))),
+            Poll::Ready(Ok(())) => {}
+        }
+
+        // Queue the initial part of the request.
+        client.head.extend_from_slice(client.method.as_str().as_bytes());
+        client.head.extend_from_slice(b" ");
+        client.head.extend_from_slice(path.as_bytes());
+        client.head.extend_from_slice(HTTP_VERSION.as_bytes());
+        // HTTP 1.1 only mandatory header is the Host one.
+        client.head.extend_from_slice(HttpHeader::Host.as_string().as_bytes());
+        client.head.extend_from_slice(host.as_bytes());
+        client.head.extend_from_slice(LINE_END.as_bytes());
+
+        client.state = ClientState::HeadersOrBody;
+        Poll::Ready(Ok(client))
+    }
+}
+
+pub struct SendRequest<'c, 'a: 'c, 'b, T: 'c> {
+    client: Option<&'c mut AsyncClient<'a, T>>,
+    body: &'b [u8],
+    head_pos: usize,
+    body_pos: usize,
+    final_state: ClientState,
+}
+
+impl<'c, 'a, 'b, T: AsyncChannel> Future for SendRequest<'c, 'a, 'b, T> {
+    type Output = Result<&'c mut AsyncClient<'a, T>, HttpError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let client = this.client.take().expect("polled after completion");
+
+        // Send what remains of the head, and then the body.
+        let mut progress = poll_send_all(&mut client.channel, cx, &client.head, &mut this.head_pos);
+        if let Poll::Ready(Ok(())) = progress {
+            progress = poll_send_all(&mut client.channel, cx, this.body, &mut this.body_pos);
+        }
+
+        match progress {
+            Poll::Pending => {
+                this.client = Some(client);
+                Poll::Pending
+            }
+            Poll::Ready(Err(err)) => Poll::Ready(Err(HttpError::from(err))),
+            Poll::Ready(Ok(())) => {
+                client.head.clear();
+                client.state = this.final_state.clone();
+                Poll::Ready(Ok(client))
+            }
+        }
+    }
+}
+
+pub struct ResponseFuture<'c, 'a: 'c, T: 'c, F> {
+    client: Option<&'c mut AsyncClient<'a, T>>,
+    filter: F,
+    head_pos: usize,
+    line: Vec<u8>,
+    status: Option<(u16, String)>,
+    headers: Vec<(HttpHeader, String)>,
+}
+
+impl<'c, 'a, T, F> Future for ResponseFuture<'c, 'a, T, F>
+    where T: AsyncChannel,
+          F: Fn(HttpHeader) -> bool + Unpin
+{
+    type Output = Result<Response<'c, T>, HttpError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let client = this.client.take().expect("polled after completion");
+
+        // Flush the head if the request had no body.
+        match poll_send_all(&mut client.channel, cx, &client.head, &mut this.head_pos) {
+            Poll::Pending => {
+                this.client = Some(client);
+                return Poll::Pending;
+            }
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(HttpError::from(err))),
+            Poll::Ready(Ok(())) => client.head.clear(),
+        }
+
+        // Read the status line and headers one byte at a time, so we don't over-read
+        // the body.
+        loop {
+            let mut next = [0u8];
+            match client.channel.poll_recv(cx, &mut next) {
+                Poll::Pending => {
+                    this.client = Some(client);
+                    return Poll::Pending;
+                }
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(HttpError::from(err))),
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(HttpError::from(ChannelError::EndOfStream)))
+                }
+                Poll::Ready(Ok(_)) => this.line.push(next[0]),
+            }
+
+            if !this.line.ends_with(LINE_END.as_bytes()) {
+                if this.line.len() > MAX_LINE_LENGTH {
+                    return Poll::Ready(Err(HttpError::from(ChannelError::BufferFull)));
+                }
+                continue;
+            }
+
+            let result = {
+                let line = match str::from_utf8(&this.line[..this.line.len() - 2]) {
+                    Ok(line) => line,
+                    Err(_) => return Poll::Ready(Err(HttpError::from(ChannelError::InvalidString))),
+                };
+
+                if this.status.is_none() {
+                    parse_status_line(line).map(|status| {
+                        this.status = Some(status);
+                        false
+                    })
+                } else if line.is_empty() {
+                    Ok(true)
+                } else {
+                    parse_header_line(line, &this.filter).map(|header| {
+                        if let Some(header) = header {
+                            this.headers.push(header);
+                        }
+                        false
+                    })
+                }
+            };
+            this.line.clear();
+
+            match result {
+                Err(err) => return Poll::Ready(Err(err)),
+                Ok(false) => continue,
+                Ok(true) => break,
+            }
+        }
+
+        let (status_code, status) = this.status.take().unwrap();
+        client.state = ClientState::Done;
+        Poll::Ready(Ok(Response {
+            status_code: status_code,
+            status: status,
+            headers: this.headers.split_off(0),
+            body: &mut client.channel,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::ptr;
+    use core::task::{RawWaker, RawWakerVTable, Waker};
+    use traits::StringChannel;
+
+    fn noop_raw_waker() -> RawWaker {
+        static VTABLE: RawWakerVTable =
+            RawWakerVTable::new(|_| noop_raw_waker(), |_| {}, |_| {}, |_| {});
+        RawWaker::new(ptr::null(), &VTABLE)
+    }
+
+    // A minimal executor, busy polling the future until completion.
+    fn block_on<F: Future>(mut future: F) -> F::Output {
+        let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+        let mut cx = Context::from_waker(&waker);
+        let mut future = unsafe { Pin::new_unchecked(&mut future) };
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    // A channel that is only ready every other poll, and records what is sent.
+    struct StutteringChannel<'a> {
+        inner: StringChannel<'a>,
+        ready: bool,
+        sent: Vec<u8>,
+    }
+
+    impl<'a> StutteringChannel<'a> {
+        fn new(data: &'a str) -> Self {
+            StutteringChannel {
+                inner: StringChannel::new(data),
+                ready: false,
+                sent: Vec::new(),
+            }
+        }
+
+        fn stutter(&mut self, cx: &mut Context) -> bool {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+            }
+            self.ready
+        }
+    }
+
+    impl<'a> AsyncChannel for StutteringChannel<'a> {
+        fn poll_open(&mut self,
+                     cx: &mut Context,
+                     _: &str,
+                     _: u16,
+                     _: bool)
+                     -> Poll<Result<(), ChannelError>> {
+            if !self.stutter(cx) {
+                return Poll::Pending;
+            }
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_send(&mut self,
+                     cx: &mut Context,
+                     data: &[u8])
+                     -> Poll<Result<usize, ChannelError>> {
+            if !self.stutter(cx) {
+                return Poll::Pending;
+            }
+            // Only accept a few bytes at a time.
+            let len = if data.len() > 5 { 5 } else { data.len() };
+            self.sent.extend_from_slice(&data[..len]);
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_recv(&mut self,
+                     cx: &mut Context,
+                     data: &mut [u8])
+                     -> Poll<Result<usize, ChannelError>> {
+            if !self.stutter(cx) {
+                return Poll::Pending;
+            }
+            let max_len = data.len();
+            Poll::Ready(self.inner.recv(data, max_len))
+        }
+    }
+
+    static RESPONSE: &'static str = "HTTP/1.1 200 OK\r\nContent-Type: text/html; \
+                                     charset=UTF-8\r\nContent-Length: 13\r\n\r\nHello World!\n";
+
+    #[test]
+    fn test_async_get() {
+        let mut client = AsyncClient::new(StutteringChannel::new(RESPONSE));
+        let client = block_on(client.get("http://localhost:8000/test.html").open()).unwrap();
+        let client = block_on(client.send(&[])).unwrap();
+        let response = block_on(client.response(|_| true)).unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.status, "OK");
+        assert_eq!(response.headers.len(), 2);
+        assert_eq!(response.headers[0],
+                   (HttpHeader::ContentType, String::from("text/html; charset=UTF-8")));
+        assert_eq!(response.headers[1],
+                   (HttpHeader::ContentLength, String::from("13")));
+        assert_eq!(response.body.sent,
+                   b"GET /test.html HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec());
+
+        let mut buffer = [0u8; 64];
+        let size = block_on(response.body.recv(&mut buffer[..13])).unwrap();
+        assert_eq!(&buffer[..size], b"Hello World!\n");
+    }
+
+    #[test]
+    fn test_async_post() {
+        let mut client = AsyncClient::new(StutteringChannel::new(RESPONSE));
+        let client = block_on(client.post("http://localhost:8000/form").open()).unwrap();
+        let client = client.header(HttpHeader::ContentLength, "7").unwrap();
+        let client = block_on(client.body(b"foo")).unwrap();
+        let client = block_on(client.send(b"=bar")).unwrap();
+        let response = block_on(client.response(|header| header == HttpHeader::ContentType))
+            .unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.headers.len(), 1);
+        assert_eq!(response.body.sent,
+                   b"POST /form HTTP/1.1\r\nHost: localhost\r\nContent-Length: 7\r\n\r\nfoo=bar"
+                       .to_vec());
+    }
+
+    #[test]
+    fn test_async_no_send() {
+        let mut client = AsyncClient::new(BlockingChannel::new(StringChannel::new(RESPONSE)));
+        let client = block_on(client.head("http://localhost:8000/test.html").open()).unwrap();
+        let response = block_on(client.response(|_| true)).unwrap();
+        assert_eq!(response.status_code, 200);
+    }
+
+    #[test]
+    fn test_async_errors() {
+        let mut client = AsyncClient::new(BlockingChannel::new(StringChannel::new("HTTP/1.2 200 \
+                                                                                   OK\r\n\r\n")));
+        let client = block_on(client.get("http://localhost/").open()).unwrap();
+        let response = block_on(client.response(|_| true));
+        assert_eq!(response.err().unwrap(), HttpError::InvalidVersion);
+
+        let mut client = AsyncClient::new(BlockingChannel::new(StringChannel::new("")));
+        let response = block_on(client.get("ftp://localhost/").open());
+        assert_eq!(response.err().unwrap(), HttpError::UnsupportedScheme);
+    }
+}
@@ -0,0 +1,455 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// A `Channel` implementation driving a Wi-Fi coprocessor running the Espressif AT
+// firmware (ESP8266 / ESP32) over a serial link, in single connection mode.
+//
+// Outgoing data is buffered and sent with AT+CIPSEND when we need to read or when the
+// buffer is full, to avoid a command round trip for each small write. Incoming data
+// arrives in `+IPD,<len>:<data>` frames that can show up at any time, including while
+// we are waiting for the result of a command, so they are always stashed in a receive
+// queue.
+
+use collections::{Vec, VecDeque};
+
+use traits::{Channel, ChannelError};
+
+// Maximum payload of a single AT+CIPSEND.
+const MAX_SEND_SIZE: usize = 2048;
+
+// Longest status line we care about. Longer lines are skipped.
+const MAX_LINE_LENGTH: usize = 128;
+
+pub trait Serial {
+    // Reads at least one byte, blocking until some data is available.
+    // Returns the number of bytes read, or an error (eg. a timeout).
+    fn read(&mut self, data: &mut [u8]) -> Result<usize, ChannelError>;
+
+    // Writes all the data.
+    fn write(&mut self, data: &[u8]) -> Result<(), ChannelError>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Event {
+    Ok,
+    Error,
+    SendOk,
+    SendFail,
+    Closed,
+    // The `>` prompt, waiting for the payload of AT+CIPSEND.
+    Prompt,
+    // A +IPD frame was added to the receive queue.
+    Data,
+}
+
+pub struct AtChannel<S> {
+    serial: S,
+    connected: bool,
+    line: Vec<u8>,
+    rx: VecDeque<u8>,
+    tx: Vec<u8>,
+    max_send_size: usize,
+}
+
+impl<S: Serial> AtChannel<S> {
+    pub fn new(serial: S) -> Self {
+        AtChannel {
+            serial: serial,
+            connected: false,
+            line: Vec::new(),
+            rx: VecDeque::new(),
+            tx: Vec::new(),
+            max_send_size: MAX_SEND_SIZE,
+        }
+    }
+
+    // Limits the size of each AT+CIPSEND, for firmwares with smaller buffers.
+    pub fn set_max_send_size(&mut self, size: usize) {
+        self.max_send_size = size;
+    }
+
+    pub fn into_inner(self) -> S {
+        self.serial
+    }
+
+    // Turns command echo off and selects the single connection mode.
+    pub fn init(&mut self) -> Result<(), ChannelError> {
+        self.command(b"ATE0\r\n")?;
+        self.command(b"AT+CIPMUX=0\r\n")
+    }
+
+    // Closes the current connection, if any.
+    pub fn close(&mut self) -> Result<(), ChannelError> {
+        self.tx.clear();
+        self.rx.clear();
+        if !self.connected {
+            return Ok(());
+        }
+        self.connected = false;
+        // The remote side may have closed the connection in the meantime, in which case
+        // we get an ERROR that we can ignore.
+        match self.command(b"AT+CIPCLOSE\r\n") {
+            Err(ChannelError::SomethingWentWrong) => Ok(()),
+            res => res,
+        }
+    }
+
+    fn command(&mut self, command: &[u8]) -> Result<(), ChannelError> {
+        self.serial.write(command)?;
+        self.wait_for(Event::Ok)
+    }
+
+    // Waits for the given event, stashing data frames on the way.
+    fn wait_for(&mut self, expected: Event) -> Result<(), ChannelError> {
+        loop {
+            match self.read_event()? {
+                event if event == expected => return Ok(()),
+                Event::Error | Event::SendFail => return Err(ChannelError::SomethingWentWrong),
+                _ => {}
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<u8, ChannelError> {
+        let mut next = [0u8];
+        while self.serial.read(&mut next)? == 0 {}
+        Ok(next[0])
+    }
+
+    // Reads from the serial link until we get something meaningful. Blank lines, echoed
+    // commands and informative messages are skipped.
+    fn read_event(&mut self) -> Result<Event, ChannelError> {
+        self.line.clear();
+        loop {
+            let value = self.read_byte()?;
+            self.line.push(value);
+
+            // The prompt is not followed by a line end.
+            if self.line == b">" {
+                return Ok(Event::Prompt);
+            }
+
+            // +IPD,<len>[,<ip>,<port>]:<data>
+            if value == b':' && self.line.starts_with(b"+IPD,") {
+                let len = parse_ipd_len(&self.line[5..self.line.len() - 1])?;
+                for _ in 0..len {
+                    let value = self.read_byte()?;
+                    self.rx.push_back(value);
+                }
+                return Ok(Event::Data);
+            }
+
+            if self.line.ends_with(b"\r\n") {
+                let event = match &self.line[..self.line.len() - 2] {
+                    b"OK" => Some(Event::Ok),
+                    b"ERROR" | b"FAIL" => Some(Event::Error),
+                    b"SEND OK" => Some(Event::SendOk),
+                    b"SEND FAIL" => Some(Event::SendFail),
+                    b"CLOSED" => Some(Event::Closed),
+                    _ => None,
+                };
+                self.line.clear();
+                if let Some(event) = event {
+                    if event == Event::Closed {
+                        self.connected = false;
+                    }
+                    return Ok(event);
+                }
+            } else if self.line.len() > MAX_LINE_LENGTH {
+                self.line.clear();
+            }
+        }
+    }
+
+    // Sends the buffered data, in as many AT+CIPSEND as needed. Unless `partial` is set,
+    // only full sized chunks are sent.
+    fn flush(&mut self, partial: bool) -> Result<(), ChannelError> {
+        while self.tx.len() >= self.max_send_size || partial && !self.tx.is_empty() {
+            let len = if self.tx.len() > self.max_send_size {
+                self.max_send_size
+            } else {
+                self.tx.len()
+            };
+
+            self.serial.write(format!("AT+CIPSEND={}\r\n", len).as_bytes())?;
+            self.wait_for(Event::Prompt)?;
+            self.serial.write(&self.tx[..len])?;
+            self.wait_for(Event::SendOk)?;
+            self.tx.drain(..len);
+        }
+        Ok(())
+    }
+}
+
+fn parse_ipd_len(header: &[u8]) -> Result<usize, ChannelError> {
+    let mut len: usize = 0;
+    let mut digits = 0;
+    for value in header.iter().take_while(|value| **value != b',') {
+        if !value.is_ascii_digit() || digits == 5 {
+            return Err(ChannelError::InvalidString);
+        }
+        len = len * 10 + (value - b'0') as usize;
+        digits += 1;
+    }
+    if digits == 0 {
+        return Err(ChannelError::InvalidString);
+    }
+    Ok(len)
+}
+
+impl<S: Serial> Channel for AtChannel<S> {
+    fn open(&mut self, host: &str, port: u16, tls: bool) -> Result<(), ChannelError> {
+        // The host is quoted in the command, and a quote or a line break would let it inject
+        // other commands.
+        if host.is_empty() || host.bytes().any(|value| value == b'"' || value.is_ascii_control()) {
+            return Err(ChannelError::InvalidHostName);
+        }
+        self.close()?;
+
+        let kind = if tls { "SSL" } else { "TCP" };
+        self.serial.write(format!("AT+CIPSTART=\"{}\",\"{}\",{}\r\n", kind, host, port)
+            .as_bytes())?;
+        self.wait_for(Event::Ok).map_err(|err| match err {
+            ChannelError::SomethingWentWrong => ChannelError::UnableToConnect,
+            err => err,
+        })?;
+        self.connected = true;
+        Ok(())
+    }
+
+    fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+        if !self.connected {
+            return Err(ChannelError::NotConnected);
+        }
+        self.tx.extend_from_slice(&data[..len]);
+        self.flush(false)?;
+        Ok(len)
+    }
+
+    fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+        // We are about to wait for an answer, make sure the request is sent.
+        if self.connected {
+            self.flush(true)?;
+        }
+
+        while self.rx.is_empty() {
+            if !self.connected {
+                return Err(ChannelError::EndOfStream);
+            }
+            self.read_event()?;
+        }
+
+        let mut i = 0;
+        while i < max_len {
+            match self.rx.pop_front() {
+                Some(value) => data[i] = value,
+                None => break,
+            }
+            i += 1;
+        }
+        Ok(i)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use collections::String;
+    use {Client, HttpHeader};
+
+    // Replays a captured AT transcript, checking what we write to the modem.
+    // Each line of the transcript is one of:
+    //   ">> " a command written by the host, followed by \r\n.
+    //   "=> " raw data written by the host.
+    //   "<< " a line sent by the modem, followed by \r\n.
+    //   "<! " raw data sent by the modem.
+    // `\r` and `\n` escapes are supported in raw data.
+    struct FakeModem {
+        // (written by the host, bytes)
+        steps: Vec<(bool, Vec<u8>)>,
+        step: usize,
+        pos: usize,
+    }
+
+    impl FakeModem {
+        fn new(transcript: &str) -> Self {
+            let mut steps = Vec::new();
+            for line in transcript.lines() {
+                let line = line.trim_start();
+                if line.is_empty() {
+                    continue;
+                }
+                let (host, data) = line.split_at(2);
+                let mut bytes = data.trim_start_matches(' ')
+                    .replace("\\r", "\r")
+                    .replace("\\n", "\n")
+                    .into_bytes();
+                match host {
+                    ">>" | "<<" => bytes.extend_from_slice(b"\r\n"),
+                    "=>" | "<!" => {}
+                    _ => panic!("Invalid transcript line: {}", line),
+                }
+                steps.push((host == ">>" || host == "=>", bytes));
+            }
+            FakeModem {
+                steps: steps,
+                step: 0,
+                pos: 0,
+            }
+        }
+
+        fn done(&self) -> bool {
+            self.step == self.steps.len()
+        }
+
+        fn advance(&mut self) {
+            if self.pos == self.steps[self.step].1.len() {
+                self.step += 1;
+                self.pos = 0;
+            }
+        }
+    }
+
+    impl Serial for FakeModem {
+        fn read(&mut self, data: &mut [u8]) -> Result<usize, ChannelError> {
+            if self.done() {
+                return Err(ChannelError::Timeout);
+            }
+            let (host, ref bytes) = self.steps[self.step];
+            assert!(!host,
+                    "Reading while the modem expects {:?}",
+                    String::from_utf8_lossy(&bytes[self.pos..]));
+            data[0] = bytes[self.pos];
+            self.pos += 1;
+            self.advance();
+            Ok(1)
+        }
+
+        fn write(&mut self, data: &[u8]) -> Result<(), ChannelError> {
+            for value in data {
+                assert!(!self.done(), "Unexpected write");
+                let (host, ref bytes) = self.steps[self.step];
+                assert!(host && bytes[self.pos] == *value,
+                        "Unexpected write {:?} at step {}",
+                        String::from_utf8_lossy(data),
+                        self.step);
+                self.pos += 1;
+                self.advance();
+            }
+            Ok(())
+        }
+    }
+
+    static GET_TRANSCRIPT: &'static str = r#"
+        >> AT+CIPSTART="TCP","example.com",80
+        << CONNECT
+        <<
+        << OK
+        >> AT+CIPSEND=47
+        << OK
+        <! >
+        => GET /index.html HTTP/1.1\r\nHost: example.com\r\n\r\n
+        <<
+        << Recv 47 bytes
+        <<
+        << SEND OK
+        <<
+        <! +IPD,38:HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n
+        <! +IPD,5:Hello
+        << CLOSED
+    "#;
+
+    #[test]
+    fn test_at_get() {
+        let mut client = Client::new(AtChannel::new(FakeModem::new(GET_TRANSCRIPT)));
+        let response = client.get("http://example.com/index.html")
+            .open()
+            .unwrap()
+            .response(|_| true)
+            .unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.headers[0],
+                   (HttpHeader::ContentLength, String::from("5")));
+
+        let mut buffer = [0u8; 16];
+        assert_eq!(response.body.read_string_to_end(&mut buffer).unwrap(), "Hello");
+        assert!(response.body.serial.done());
+        // Already closed by the remote side.
+        response.body.close().unwrap();
+    }
+
+    static CHUNKED_TRANSCRIPT: &'static str = r#"
+        >> ATE0
+        << ATE0
+        <<
+        << OK
+        >> AT+CIPMUX=0
+        <<
+        << OK
+        >> AT+CIPSTART="SSL","10.0.0.1",443
+        << CONNECT
+        <<
+        << OK
+        >> AT+CIPSEND=8
+        << OK
+        <! >
+        => 01234567
+        <! +IPD,3,10.0.0.1,443:abc
+        <<
+        << SEND OK
+        >> AT+CIPSEND=2
+        << OK
+        <! >
+        => 89
+        << SEND FAIL
+        >> AT+CIPCLOSE
+        << ERROR
+        >> AT+CIPSTART="TCP","unknown.host",80
+        << DNS Fail
+        <<
+        << ERROR
+    "#;
+
+    #[test]
+    fn test_at_framing() {
+        let mut channel = AtChannel::new(FakeModem::new(CHUNKED_TRANSCRIPT));
+        channel.set_max_send_size(8);
+        channel.init().unwrap();
+        channel.open("10.0.0.1", 443, true).unwrap();
+
+        // Reaching the send size flushes the first 8 bytes, with data coming in meanwhile.
+        assert_eq!(channel.send(b"0123456789", 10).unwrap(), 10);
+        assert_eq!(channel.rx.len(), 3);
+
+        // Reading flushes the remaining data first.
+        let mut buffer = [0u8; 4];
+        assert_eq!(channel.recv(&mut buffer, 4).err().unwrap(),
+                   ChannelError::SomethingWentWrong);
+
+        // The queued data is dropped when reopening.
+        assert_eq!(channel.open("unknown.host", 80, false).err().unwrap(),
+                   ChannelError::UnableToConnect);
+        assert!(channel.rx.is_empty());
+        assert!(channel.serial.done());
+    }
+
+    #[test]
+    fn test_at_invalid_host() {
+        let mut channel = AtChannel::new(FakeModem::new(""));
+        for host in &["", "evil\",\"1\r\nAT+RST", "example.com\r\n", "a\tb"] {
+            assert_eq!(channel.open(host, 80, false).err().unwrap(),
+                       ChannelError::InvalidHostName);
+        }
+        assert!(channel.serial.done());
+    }
+
+    #[test]
+    fn test_ipd_len() {
+        assert_eq!(parse_ipd_len(b"42").unwrap(), 42);
+        assert_eq!(parse_ipd_len(b"5,192.168.1.1,80").unwrap(), 5);
+        assert!(parse_ipd_len(b"").is_err());
+        assert!(parse_ipd_len(b"4a").is_err());
+        assert!(parse_ipd_len(b"123456").is_err());
+    }
+}
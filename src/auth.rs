@@ -0,0 +1,373 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Client authentication schemes.
+
+use collections::{String, Vec};
+
+use base64;
+use md5::Md5;
+use sha256::Sha256;
+use traits::Entropy;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Credentials<'c> {
+    // User and password (RFC 7617).
+    Basic(&'c str, &'c str),
+    // An OAuth 2.0 access token (RFC 6750).
+    Bearer(&'c str),
+}
+
+impl<'c> Credentials<'c> {
+    // The value of the Authorization header.
+    pub fn header_value(&self) -> String {
+        match *self {
+            Credentials::Basic(user, password) => basic_auth(user, password),
+            Credentials::Bearer(token) => format!("Bearer {}", token),
+        }
+    }
+}
+
+pub fn basic_auth(user: &str, password: &str) -> String {
+    format!("Basic {}", base64::encode(format!("{}:{}", user, password).as_bytes()))
+}
+
+// The hash function of a Digest challenge. The session variants hash the client nonce into
+// the credentials.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DigestAlgorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+
+impl DigestAlgorithm {
+    fn from_token(token: &str) -> Option<DigestAlgorithm> {
+        let algorithms = [("MD5", DigestAlgorithm::Md5),
+                          ("MD5-sess", DigestAlgorithm::Md5Sess),
+                          ("SHA-256", DigestAlgorithm::Sha256),
+                          ("SHA-256-sess", DigestAlgorithm::Sha256Sess)];
+        algorithms.iter()
+            .find(|algorithm| algorithm.0.eq_ignore_ascii_case(token))
+            .map(|algorithm| algorithm.1)
+    }
+
+    fn as_str(&self) -> &str {
+        match *self {
+            DigestAlgorithm::Md5 => "MD5",
+            DigestAlgorithm::Md5Sess => "MD5-sess",
+            DigestAlgorithm::Sha256 => "SHA-256",
+            DigestAlgorithm::Sha256Sess => "SHA-256-sess",
+        }
+    }
+
+    fn is_session(&self) -> bool {
+        *self == DigestAlgorithm::Md5Sess || *self == DigestAlgorithm::Sha256Sess
+    }
+
+    // The lowercase hex hash of the parts, joined with colons.
+    fn hash(&self, parts: &[&str]) -> String {
+        match *self {
+            DigestAlgorithm::Md5 | DigestAlgorithm::Md5Sess => {
+                let mut md5 = Md5::new();
+                for (i, part) in parts.iter().enumerate() {
+                    if i != 0 {
+                        md5.update(b":");
+                    }
+                    md5.update(part.as_bytes());
+                }
+                hex(&md5.digest())
+            }
+            DigestAlgorithm::Sha256 | DigestAlgorithm::Sha256Sess => {
+                let mut sha256 = Sha256::new();
+                for (i, part) in parts.iter().enumerate() {
+                    if i != 0 {
+                        sha256.update(b":");
+                    }
+                    sha256.update(part.as_bytes());
+                }
+                hex(&sha256.digest())
+            }
+        }
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    let mut res = String::with_capacity(data.len() * 2);
+    for value in data {
+        res.push_str(&format!("{:02x}", value));
+    }
+    res
+}
+
+// A `WWW-Authenticate: Digest` challenge (RFC 7616).
+#[derive(Clone, Debug, PartialEq)]
+pub struct DigestChallenge {
+    pub realm: String,
+    pub nonce: String,
+    pub opaque: Option<String>,
+    pub algorithm: DigestAlgorithm,
+    // Whether the server asked for the "auth" quality of protection. Without it, the response
+    // is computed as in RFC 2069.
+    pub qop_auth: bool,
+    // The previous nonce expired, but the credentials were right.
+    pub stale: bool,
+}
+
+impl DigestChallenge {
+    // Returns None if this is not a Digest challenge, or if we can't answer it: an unknown
+    // algorithm, or a server only accepting "auth-int".
+    pub fn parse(value: &str) -> Option<DigestChallenge> {
+        let mut parts = value.trim().splitn(2, ' ');
+        if !parts.next()?.eq_ignore_ascii_case("Digest") {
+            return None;
+        }
+        let params = parse_params(parts.next()?)?;
+        let param = |name: &str| {
+            params.iter()
+                .find(|param| param.0.eq_ignore_ascii_case(name))
+                .map(|param| param.1.as_str())
+        };
+
+        let algorithm = match param("algorithm") {
+            Some(algorithm) => DigestAlgorithm::from_token(algorithm)?,
+            None => DigestAlgorithm::Md5,
+        };
+        let qop_auth = match param("qop") {
+            Some(qop) => {
+                if !qop.split(',').any(|qop| qop.trim().eq_ignore_ascii_case("auth")) {
+                    return None;
+                }
+                true
+            }
+            None => false,
+        };
+
+        Some(DigestChallenge {
+            realm: String::from(param("realm")?),
+            nonce: String::from(param("nonce")?),
+            opaque: param("opaque").map(String::from),
+            algorithm: algorithm,
+            qop_auth: qop_auth,
+            stale: param("stale").map_or(false, |stale| stale.eq_ignore_ascii_case("true")),
+        })
+    }
+
+    // The value of the Authorization header answering this challenge. `nonce_count` is the
+    // number of requests already sent with this nonce, including this one.
+    pub fn authorization(&self,
+                         user: &str,
+                         password: &str,
+                         method: &str,
+                         uri: &str,
+                         cnonce: &str,
+                         nonce_count: u32)
+                         -> String {
+        let algorithm = self.algorithm;
+        let mut ha1 = algorithm.hash(&[user, &self.realm, password]);
+        if algorithm.is_session() {
+            ha1 = algorithm.hash(&[&ha1, &self.nonce, cnonce]);
+        }
+        let ha2 = algorithm.hash(&[method, uri]);
+        let nc = format!("{:08x}", nonce_count);
+
+        let mut res = format!("Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", \
+                               algorithm={}",
+                              quote(user),
+                              quote(&self.realm),
+                              quote(&self.nonce),
+                              quote(uri),
+                              algorithm.as_str());
+        if self.qop_auth {
+            let response = algorithm.hash(&[&ha1, &self.nonce, &nc, cnonce, "auth", &ha2]);
+            res.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\", response=\"{}\"",
+                                  nc,
+                                  quote(cnonce),
+                                  response));
+        } else {
+            let response = algorithm.hash(&[&ha1, &self.nonce, &ha2]);
+            res.push_str(&format!(", response=\"{}\"", response));
+        }
+        if let Some(ref opaque) = self.opaque {
+            res.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+        }
+        res
+    }
+}
+
+// Escapes a quoted-string value.
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Splits comma separated `name=value` auth parameters, unquoting the values.
+fn parse_params(value: &str) -> Option<Vec<(String, String)>> {
+    let mut params = Vec::new();
+    let mut chars = value.chars().peekable();
+    loop {
+        while chars.peek().map_or(false, |c| *c == ',' || c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            return Some(params);
+        }
+
+        let mut name = String::new();
+        while let Some(c) = chars.next() {
+            if c == '=' {
+                break;
+            }
+            name.push(c);
+        }
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => value.push(chars.next()?),
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.peek().cloned() {
+                if c == ',' {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+        }
+        params.push((String::from(name.trim()), String::from(value.trim())));
+    }
+}
+
+/// Answers Digest challenges for a user, counting the requests sent with each server nonce.
+pub struct DigestAuth<'c> {
+    user: &'c str,
+    password: &'c str,
+    cnonce: String,
+    challenge: Option<DigestChallenge>,
+    nonce_count: u32,
+}
+
+impl<'c> DigestAuth<'c> {
+    // The client nonce is random, and reused with an increasing nonce count.
+    pub fn new<E: Entropy>(user: &'c str, password: &'c str, entropy: &mut E) -> Self {
+        let mut random = [0u8; 16];
+        entropy.fill(&mut random);
+        DigestAuth {
+            user: user,
+            password: password,
+            cnonce: hex(&random),
+            challenge: None,
+            nonce_count: 0,
+        }
+    }
+
+    pub fn challenge(&self) -> Option<&DigestChallenge> {
+        self.challenge.as_ref()
+    }
+
+    // Uses a new challenge for the next requests. The nonce count restarts with a new nonce.
+    pub fn set_challenge(&mut self, challenge: DigestChallenge) {
+        if self.challenge.as_ref().map_or(true, |current| current.nonce != challenge.nonce) {
+            self.nonce_count = 0;
+        }
+        self.challenge = Some(challenge);
+    }
+
+    // The Authorization header for the next request, or None until we got a challenge.
+    pub fn authorization(&mut self, method: &str, uri: &str) -> Option<String> {
+        let challenge = self.challenge.as_ref()?;
+        self.nonce_count += 1;
+        Some(challenge.authorization(self.user,
+                                     self.password,
+                                     method,
+                                     uri,
+                                     &self.cnonce,
+                                     self.nonce_count))
+    }
+}
+
+#[test]
+fn test_credentials() {
+    assert_eq!(Credentials::Basic("Aladdin", "open sesame").header_value(),
+               "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+    assert_eq!(Credentials::Basic("user", "").header_value(), "Basic dXNlcjo=");
+    assert_eq!(Credentials::Bearer("mF_9.B5f-4.1JqM").header_value(),
+               "Bearer mF_9.B5f-4.1JqM");
+}
+
+#[test]
+fn test_digest_challenge() {
+    let challenge = DigestChallenge::parse("Digest realm=\"http-auth@example.org\", \
+                                            qop=\"auth, auth-int\", algorithm=SHA-256, \
+                                            nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+                                            opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"")
+        .unwrap();
+    assert_eq!(challenge.realm, "http-auth@example.org");
+    assert_eq!(challenge.nonce, "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v");
+    assert_eq!(challenge.opaque,
+               Some(String::from("FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS")));
+    assert_eq!(challenge.algorithm, DigestAlgorithm::Sha256);
+    assert!(challenge.qop_auth);
+    assert!(!challenge.stale);
+
+    let challenge = DigestChallenge::parse("digest realm=\"a \\\"b\\\", c\",nonce=xyz,stale=TRUE")
+        .unwrap();
+    assert_eq!(challenge.realm, "a \"b\", c");
+    assert_eq!(challenge.nonce, "xyz");
+    assert_eq!(challenge.algorithm, DigestAlgorithm::Md5);
+    assert!(!challenge.qop_auth);
+    assert!(challenge.stale);
+
+    assert_eq!(DigestChallenge::parse("Basic realm=\"test\""), None);
+    assert_eq!(DigestChallenge::parse("Digest realm=\"test\""), None);
+    assert_eq!(DigestChallenge::parse("Digest realm=\"a\", nonce=\"b\", qop=\"auth-int\""), None);
+    assert_eq!(DigestChallenge::parse("Digest realm=\"a\", nonce=\"b\", algorithm=SHA-512-256"),
+               None);
+    assert_eq!(DigestChallenge::parse("Digest realm=\"a\", nonce=\"b"), None);
+}
+
+#[test]
+fn test_digest_authorization() {
+    // The examples of RFC 7616, section 3.9.1.
+    let mut challenge = DigestChallenge {
+        realm: String::from("http-auth@example.org"),
+        nonce: String::from("7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v"),
+        opaque: Some(String::from("FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS")),
+        algorithm: DigestAlgorithm::Md5,
+        qop_auth: true,
+        stale: false,
+    };
+    let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+    assert_eq!(challenge.authorization("Mufasa", "Circle of Life", "GET", "/dir/index.html",
+                                       cnonce, 1),
+               "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+                nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", uri=\"/dir/index.html\", \
+                algorithm=MD5, qop=auth, nc=00000001, \
+                cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", \
+                response=\"8ca523f5e9506fed4657c9700eebdbec\", \
+                opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"");
+
+    challenge.algorithm = DigestAlgorithm::Sha256;
+    assert!(challenge.authorization("Mufasa", "Circle of Life", "GET", "/dir/index.html",
+                                    cnonce, 1)
+        .contains("response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""));
+
+    // Without qop, as in RFC 2069.
+    let challenge = DigestChallenge {
+        realm: String::from("testrealm@host.com"),
+        nonce: String::from("dcd98b7102dd2f0e8b11d0f600bfb0c093"),
+        opaque: None,
+        algorithm: DigestAlgorithm::Md5,
+        qop_auth: false,
+        stale: false,
+    };
+    assert!(challenge.authorization("Mufasa", "Circle Of Life", "GET", "/dir/index.html", "", 1)
+        .ends_with("algorithm=MD5, response=\"670fd8c2df070c60b045671b8b24ff02\""));
+}
@@ -0,0 +1,89 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Base64 with the standard alphabet and padding (RFC 4648).
+
+use collections::{String, Vec};
+
+static ALPHABET: &'static [u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(data: &[u8]) -> String {
+    let mut res = String::with_capacity(data.len() / 3 * 4 + 4);
+    for chunk in data.chunks(3) {
+        let value = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 |
+                    *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                res.push(ALPHABET[(value >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+    res
+}
+
+fn decode_char(value: u8) -> Option<u32> {
+    let value = match value {
+        b'A'..=b'Z' => value - b'A',
+        b'a'..=b'z' => value - b'a' + 26,
+        b'0'..=b'9' => value - b'0' + 52,
+        b'+' => 62,
+        b'/' => 63,
+        _ => return None,
+    };
+    Some(value as u32)
+}
+
+// Returns None if the input is not valid base64.
+pub fn decode(data: &str) -> Option<Vec<u8>> {
+    let data = data.as_bytes();
+    let mut res = Vec::with_capacity(data.len() / 4 * 3);
+    for (index, chunk) in data.chunks(4).enumerate() {
+        if chunk.len() != 4 {
+            return None;
+        }
+
+        // Padding is only allowed at the end.
+        let padding = chunk.iter().rev().take_while(|value| **value == b'=').count();
+        if padding > 2 || (padding > 0 && (index + 1) * 4 != data.len()) {
+            return None;
+        }
+
+        let mut value = 0;
+        for c in &chunk[..4 - padding] {
+            value = value << 6 | decode_char(*c)?;
+        }
+        value <<= 6 * padding;
+
+        res.push((value >> 16) as u8);
+        if padding < 2 {
+            res.push((value >> 8) as u8);
+        }
+        if padding < 1 {
+            res.push(value as u8);
+        }
+    }
+    Some(res)
+}
+
+#[test]
+fn test_base64() {
+    assert_eq!(encode(b""), "");
+    assert_eq!(encode(b"f"), "Zg==");
+    assert_eq!(encode(b"fo"), "Zm8=");
+    assert_eq!(encode(b"foo"), "Zm9v");
+    assert_eq!(encode(b"foobar"), "Zm9vYmFy");
+    assert_eq!(encode(&[0xfb, 0xff]), "+/8=");
+
+    assert_eq!(decode("").unwrap(), b"");
+    assert_eq!(decode("Zg==").unwrap(), b"f");
+    assert_eq!(decode("Zm8=").unwrap(), b"fo");
+    assert_eq!(decode("Zm9vYmFy").unwrap(), b"foobar");
+    assert_eq!(decode("+/8=").unwrap(), [0xfb, 0xff]);
+    assert_eq!(decode("Zm9"), None);
+    assert_eq!(decode("Zg==Zg=="), None);
+    assert_eq!(decode("Z==="), None);
+    assert_eq!(decode("Zm9*"), None);
+}
@@ -0,0 +1,65 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// The block buffering and padding shared by the MD5, SHA-1 and SHA-256 hashes.
+
+/// Splits the hashed data into 64 bytes blocks, and pads the last one.
+pub struct BlockBuffer {
+    block: [u8; 64],
+    block_len: usize,
+    length: u64,
+}
+
+impl BlockBuffer {
+    pub fn new() -> Self {
+        BlockBuffer {
+            block: [0; 64],
+            block_len: 0,
+            length: 0,
+        }
+    }
+
+    // Calls `process` with each block completed by `data`.
+    pub fn update<F>(&mut self, data: &[u8], mut process: F)
+        where F: FnMut(&[u8; 64])
+    {
+        self.length += data.len() as u64;
+        for value in data {
+            self.block[self.block_len] = *value;
+            self.block_len += 1;
+            if self.block_len == 64 {
+                process(&self.block);
+                self.block_len = 0;
+            }
+        }
+    }
+
+    // Pads with a 1 bit, zeros and the message length in bits, encoded with `encode_length`,
+    // to a multiple of 64 bytes.
+    pub fn finish<F>(mut self, encode_length: fn(u64) -> [u8; 8], mut process: F)
+        where F: FnMut(&[u8; 64])
+    {
+        let bits = self.length * 8;
+        self.block[self.block_len] = 0x80;
+        self.block_len += 1;
+        if self.block_len > 56 {
+            for value in self.block[self.block_len..].iter_mut() {
+                *value = 0;
+            }
+            process(&self.block);
+            self.block_len = 0;
+        }
+        for value in self.block[self.block_len..56].iter_mut() {
+            *value = 0;
+        }
+        self.block[56..].copy_from_slice(&encode_length(bits));
+        process(&self.block);
+    }
+}
+
+impl Default for BlockBuffer {
+    fn default() -> Self {
+        BlockBuffer::new()
+    }
+}
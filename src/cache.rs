@@ -0,0 +1,483 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// A small private HTTP cache (RFC 7234) for GET requests: fresh responses are served from the
+// cache, and stale ones are revalidated with conditional requests (RFC 7232).
+
+use collections::{String, Vec};
+use core::cmp;
+use core::str::FromStr;
+
+use chunked::ChunkedReader;
+use traits::{Channel, ChannelError};
+use {Client, HttpError, HttpHeader};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    // When the response was received or last validated, in seconds since the Unix epoch.
+    pub date: u64,
+    // The freshness lifetime, in seconds. Entries without one are validated every time.
+    pub max_age: Option<u64>,
+    // The response must be validated before each use.
+    pub no_cache: bool,
+    pub body: Vec<u8>,
+}
+
+impl Entry {
+    pub fn is_fresh(&self, now: u64) -> bool {
+        !self.no_cache &&
+        self.max_age.map_or(false, |max_age| now < self.date.saturating_add(max_age))
+    }
+
+    // Entries without a validator can't be revalidated.
+    fn has_validator(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+}
+
+// Where cached responses are kept, eg. in memory or in a flash file system. Storages may
+// drop entries at any time to stay within their bounds.
+pub trait CacheStorage {
+    fn get(&mut self, url: &str) -> Option<Entry>;
+
+    // Stores an entry, replacing the one with the same url.
+    fn put(&mut self, entry: Entry);
+
+    fn remove(&mut self, url: &str);
+}
+
+/// Keeps entries in memory up to a total body size, evicting the least recently used ones.
+pub struct MemoryCache {
+    // The entries, with the sequence number of their last use.
+    entries: Vec<(u64, Entry)>,
+    max_size: usize,
+    size: usize,
+    sequence: u64,
+}
+
+impl MemoryCache {
+    pub fn new(max_size: usize) -> Self {
+        MemoryCache {
+            entries: Vec::new(),
+            max_size: max_size,
+            size: 0,
+            sequence: 0,
+        }
+    }
+
+    // The total size of the cached bodies.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.size = 0;
+    }
+}
+
+impl CacheStorage for MemoryCache {
+    fn get(&mut self, url: &str) -> Option<Entry> {
+        self.sequence += 1;
+        let sequence = self.sequence;
+        self.entries.iter_mut().find(|entry| entry.1.url == url).map(|entry| {
+            entry.0 = sequence;
+            entry.1.clone()
+        })
+    }
+
+    fn put(&mut self, entry: Entry) {
+        self.remove(&entry.url);
+        if entry.body.len() > self.max_size {
+            return;
+        }
+        while self.size + entry.body.len() > self.max_size {
+            let oldest = self.entries
+                .iter()
+                .enumerate()
+                .min_by_key(|entry| (entry.1).0)
+                .map(|entry| entry.0)
+                .unwrap();
+            self.size -= self.entries.remove(oldest).1.body.len();
+        }
+        self.sequence += 1;
+        self.size += entry.body.len();
+        self.entries.push((self.sequence, entry));
+    }
+
+    fn remove(&mut self, url: &str) {
+        if let Some(pos) = self.entries.iter().position(|entry| entry.1.url == url) {
+            self.size -= self.entries.remove(pos).1.body.len();
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    // A fresh response, served without a request.
+    Cache,
+    // A stale response, validated by the server with a 304 status.
+    Revalidated,
+    Network,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CachedResponse {
+    pub source: Source,
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheControl {
+    pub max_age: Option<u64>,
+    pub no_cache: bool,
+    pub no_store: bool,
+}
+
+impl CacheControl {
+    // Parses the directives of Cache-Control header values, ignoring the unknown ones.
+    pub fn parse(value: &str) -> CacheControl {
+        let mut res = CacheControl::default();
+        for directive in value.split(',') {
+            let mut parts = directive.splitn(2, '=');
+            let name = parts.next().unwrap().trim();
+            let argument = parts.next().map(|argument| argument.trim().trim_matches('"'));
+            if name.eq_ignore_ascii_case("max-age") {
+                // A max-age that can't be parsed makes the response stale.
+                res.max_age = Some(argument.and_then(|value| u64::from_str(value).ok())
+                    .unwrap_or(0));
+            } else if name.eq_ignore_ascii_case("no-cache") {
+                res.no_cache = true;
+            } else if name.eq_ignore_ascii_case("no-store") {
+                res.no_store = true;
+            }
+        }
+        res
+    }
+}
+
+pub struct HttpCache<'s, S: 's> {
+    storage: &'s mut S,
+    // The largest body read from responses.
+    max_body_size: usize,
+}
+
+impl<'s, S: CacheStorage> HttpCache<'s, S> {
+    pub fn new(storage: &'s mut S, max_body_size: usize) -> Self {
+        HttpCache {
+            storage: storage,
+            max_body_size: max_body_size,
+        }
+    }
+
+    // Gets the body of a resource, from the cache if it's fresh at `now`, in seconds since the
+    // Unix epoch. Only 200 responses are cached, other statuses are returned as errors.
+    pub fn get<'u, T>(&mut self,
+                      client: &mut Client<'u, T>,
+                      url: &'u str,
+                      now: u64)
+                      -> Result<CachedResponse, HttpError>
+        where T: Channel
+    {
+        let entry = self.storage.get(url);
+        if let Some(ref entry) = entry {
+            if entry.is_fresh(now) {
+                return Ok(CachedResponse {
+                    source: Source::Cache,
+                    body: entry.body.clone(),
+                });
+            }
+        }
+        let entry = entry.filter(|entry| entry.has_validator());
+
+        let request = client.get(url).open()?;
+        if let Some(ref entry) = entry {
+            if let Some(ref etag) = entry.etag {
+                request.header(HttpHeader::IfNoneMatch, etag)?;
+            }
+            if let Some(ref last_modified) = entry.last_modified {
+                request.header(HttpHeader::IfModifiedSince, last_modified)?;
+            }
+        }
+        let response = request.response(|header| {
+                header == HttpHeader::CacheControl || header == HttpHeader::ContentLength ||
+                header == HttpHeader::Etag ||
+                header == HttpHeader::LastModified ||
+                header == HttpHeader::TransferEncoding
+            })?;
+
+        let headers = &response.headers;
+        let header = |name: HttpHeader| {
+            headers.iter()
+                .find(|header| header.0 == name)
+                .map(|header| header.1.trim())
+        };
+        // Cache-Control may be split over several headers.
+        let mut cache_control = CacheControl::default();
+        let mut has_cache_control = false;
+        for header in headers.iter().filter(|header| header.0 == HttpHeader::CacheControl) {
+            has_cache_control = true;
+            let directives = CacheControl::parse(&header.1);
+            cache_control.max_age = directives.max_age.or(cache_control.max_age);
+            cache_control.no_cache |= directives.no_cache;
+            cache_control.no_store |= directives.no_store;
+        }
+
+        let (source, mut entry) = match (response.status_code, entry) {
+            (304, Some(mut entry)) => {
+                // The 304 response updates the metadata of the stored one.
+                if let Some(etag) = header(HttpHeader::Etag) {
+                    entry.etag = Some(String::from(etag));
+                }
+                if let Some(last_modified) = header(HttpHeader::LastModified) {
+                    entry.last_modified = Some(String::from(last_modified));
+                }
+                (Source::Revalidated, entry)
+            }
+            (200, _) => {
+                let chunked = header(HttpHeader::TransferEncoding)
+                    .map_or(false, |value| value.eq_ignore_ascii_case("chunked"));
+                let length = header(HttpHeader::ContentLength)
+                    .and_then(|value| usize::from_str(value).ok());
+                let body = if chunked {
+                    read_body(&mut ChunkedReader::new(response.body), None, self.max_body_size)?
+                } else {
+                    read_body(response.body, length, self.max_body_size)?
+                };
+                let entry = Entry {
+                    url: String::from(url),
+                    etag: header(HttpHeader::Etag).map(String::from),
+                    last_modified: header(HttpHeader::LastModified).map(String::from),
+                    date: now,
+                    max_age: None,
+                    no_cache: false,
+                    body: body,
+                };
+                (Source::Network, entry)
+            }
+            (status, _) => return Err(HttpError::UnexpectedStatus(status)),
+        };
+
+        entry.date = now;
+        // A 304 response only updates the directives it carries (RFC 7234, section 4.3.4).
+        if source == Source::Network || has_cache_control {
+            entry.max_age = cache_control.max_age;
+            entry.no_cache = cache_control.no_cache;
+        }
+        if cache_control.no_store {
+            self.storage.remove(url);
+            return Ok(CachedResponse {
+                source: source,
+                body: entry.body,
+            });
+        }
+        let body = entry.body.clone();
+        self.storage.put(entry);
+        Ok(CachedResponse {
+            source: source,
+            body: body,
+        })
+    }
+}
+
+// Reads a body of `length` bytes, or up to the end of the stream.
+fn read_body<C: Channel>(body: &mut C,
+                         length: Option<usize>,
+                         max_size: usize)
+                         -> Result<Vec<u8>, HttpError> {
+    if length.map_or(false, |length| length > max_size) {
+        return Err(HttpError::BodyTooLarge);
+    }
+    let mut res = Vec::new();
+    let mut buffer = [0u8; 256];
+    loop {
+        // Read one more byte than allowed to detect larger bodies.
+        let remaining = length.unwrap_or(max_size + 1) - res.len();
+        let size = cmp::min(remaining, buffer.len());
+        if size == 0 {
+            break;
+        }
+        let read = body.read_to_end(&mut buffer, size)?;
+        res.extend_from_slice(&buffer[..read]);
+        if res.len() > max_size {
+            return Err(HttpError::BodyTooLarge);
+        }
+        if read < size {
+            break;
+        }
+    }
+    // A body cut short must not be stored.
+    if length.map_or(false, |length| res.len() != length) {
+        return Err(HttpError::ChannelError(ChannelError::EndOfStream));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::str;
+    use traits::MemoryChannel;
+
+    fn entry(url: &str, size: usize) -> Entry {
+        Entry {
+            url: String::from(url),
+            etag: None,
+            last_modified: None,
+            date: 0,
+            max_age: None,
+            no_cache: false,
+            body: vec![0; size],
+        }
+    }
+
+    #[test]
+    fn test_cache_control() {
+        assert_eq!(CacheControl::parse("public, max-age=60"),
+                   CacheControl {
+                       max_age: Some(60),
+                       no_cache: false,
+                       no_store: false,
+                   });
+        assert_eq!(CacheControl::parse("No-Cache, max-age=\"soon\", no-store"),
+                   CacheControl {
+                       max_age: Some(0),
+                       no_cache: true,
+                       no_store: true,
+                   });
+    }
+
+    #[test]
+    fn test_freshness() {
+        let mut entry = entry("a", 0);
+        entry.date = 1000;
+        assert!(!entry.is_fresh(1000));
+        entry.max_age = Some(u64::max_value());
+        assert!(entry.is_fresh(u64::max_value() - 1));
+        entry.no_cache = true;
+        assert!(!entry.is_fresh(1000));
+    }
+
+    #[test]
+    fn test_memory_cache() {
+        let mut cache = MemoryCache::new(10);
+        cache.put(entry("a", 4));
+        cache.put(entry("b", 4));
+        assert!(cache.get("a").is_some());
+        // Evicts b, that wasn't used since a was.
+        cache.put(entry("c", 4));
+        assert!(cache.get("b").is_none());
+        assert_eq!(cache.size(), 8);
+        cache.put(entry("a", 2));
+        assert_eq!(cache.size(), 6);
+        assert_eq!(cache.len(), 2);
+        // Too large to be cached at all.
+        cache.put(entry("d", 11));
+        assert!(cache.get("d").is_none());
+        cache.remove("c");
+        assert_eq!(cache.size(), 2);
+    }
+
+    #[test]
+    fn test_http_cache() {
+        let data = "HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nETag: \
+                    \"v1\"\r\nContent-Length: 6\r\n\r\nconfigHTTP/1.1 304 Not \
+                    Modified\r\nCache-Control: max-age=60\r\n\r\nHTTP/1.1 200 OK\r\nETag: \
+                    \"v2\"\r\nCache-Control: no-store\r\nTransfer-Encoding: \
+                    chunked\r\n\r\n7\r\nconfig2\r\n0\r\n\r\n";
+        let mut channel = MemoryChannel::new(data.as_bytes());
+        let mut storage = MemoryCache::new(1024);
+        {
+            let mut client = Client::new(&mut channel);
+            let mut cache = HttpCache::new(&mut storage, 256);
+            let url = "http://localhost/config";
+            let response = cache.get(&mut client, url, 1000).unwrap();
+            assert_eq!(response.source, Source::Network);
+            assert_eq!(response.body, b"config");
+            assert_eq!(cache.get(&mut client, url, 1059).unwrap().source, Source::Cache);
+            let response = cache.get(&mut client, url, 1060).unwrap();
+            assert_eq!(response.source, Source::Revalidated);
+            assert_eq!(response.body, b"config");
+            assert_eq!(cache.get(&mut client, url, 1100).unwrap().source, Source::Cache);
+            let response = cache.get(&mut client, url, 1120).unwrap();
+            assert_eq!(response.source, Source::Network);
+            assert_eq!(response.body, b"config2");
+        }
+        assert!(storage.is_empty());
+        assert_eq!(str::from_utf8(channel.sent()).unwrap(),
+                   "GET /config HTTP/1.1\r\nHost: localhost\r\n\r\nGET /config \
+                    HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: \"v1\"\r\n\r\nGET /config \
+                    HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: \"v1\"\r\n\r\n");
+    }
+
+    #[test]
+    fn test_http_cache_errors() {
+        let data = "HTTP/1.1 200 OK\r\nLast-Modified: Tue, 15 Nov 1994 12:45:26 \
+                    GMT\r\nContent-Length: 6\r\n\r\nconfigHTTP/1.1 500 Internal Server \
+                    Error\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 300\r\n\r\n";
+        let mut channel = MemoryChannel::new(data.as_bytes());
+        let mut storage = MemoryCache::new(1024);
+        {
+            let mut client = Client::new(&mut channel);
+            let mut cache = HttpCache::new(&mut storage, 256);
+            let url = "http://localhost/config";
+            // Without max-age, the response is validated every time.
+            assert_eq!(cache.get(&mut client, url, 0).unwrap().source, Source::Network);
+            assert_eq!(cache.get(&mut client, url, 0), Err(HttpError::UnexpectedStatus(500)));
+            assert_eq!(cache.get(&mut client, url, 0), Err(HttpError::BodyTooLarge));
+        }
+        assert_eq!(storage.len(), 1);
+        assert!(str::from_utf8(channel.sent())
+            .unwrap()
+            .ends_with("If-Modified-Since: Tue, 15 Nov 1994 12:45:26 GMT\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_http_cache_truncated() {
+        let data = "HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nContent-Length: \
+                    10\r\n\r\nconf";
+        let mut channel = MemoryChannel::new(data.as_bytes());
+        let mut storage = MemoryCache::new(1024);
+        {
+            let mut client = Client::new(&mut channel);
+            let mut cache = HttpCache::new(&mut storage, 256);
+            assert_eq!(cache.get(&mut client, "http://localhost/config", 0),
+                       Err(HttpError::ChannelError(ChannelError::EndOfStream)));
+        }
+        assert!(storage.is_empty());
+    }
+
+    #[test]
+    fn test_http_cache_304_without_cache_control() {
+        let data = "HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nETag: \
+                    \"v1\"\r\nContent-Length: 6\r\n\r\nconfigHTTP/1.1 304 Not \
+                    Modified\r\n\r\n";
+        let mut channel = MemoryChannel::new(data.as_bytes());
+        let mut storage = MemoryCache::new(1024);
+        {
+            let mut client = Client::new(&mut channel);
+            let mut cache = HttpCache::new(&mut storage, 256);
+            let url = "http://localhost/config";
+            assert_eq!(cache.get(&mut client, url, 0).unwrap().source, Source::Network);
+            assert_eq!(cache.get(&mut client, url, 60).unwrap().source, Source::Revalidated);
+            // The stored max-age is kept, and applies from the validation.
+            assert_eq!(cache.get(&mut client, url, 119).unwrap().source, Source::Cache);
+        }
+        assert_eq!(storage.len(), 1);
+        assert!(str::from_utf8(channel.sent())
+            .unwrap()
+            .ends_with("If-None-Match: \"v1\"\r\n\r\n"));
+    }
+}
@@ -0,0 +1,93 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// The checksums of the gzip (RFC 1952) and zlib (RFC 1950) formats.
+
+// CRC-32 of the reflected 0xedb88320 polynomial, one nibble at a time to keep the table small.
+static CRC_TABLE: [u32; 16] = [0x00000000, 0x1db71064, 0x3b6e20c8, 0x26d930ac, 0x76dc4190,
+                               0x6b6b51f4, 0x4db26158, 0x5005713c, 0xedb88320, 0xf00f9344,
+                               0xd6d6a3e8, 0xcb61b38c, 0x9b64c2b0, 0x86d3d2d4, 0xa00ae278,
+                               0xbdbdf21c];
+
+pub struct Crc32 {
+    crc: u32,
+    length: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32 {
+            crc: 0xffffffff,
+            length: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for value in data {
+            let mut crc = self.crc ^ *value as u32;
+            crc = (crc >> 4) ^ CRC_TABLE[(crc & 0xf) as usize];
+            crc = (crc >> 4) ^ CRC_TABLE[(crc & 0xf) as usize];
+            self.crc = crc;
+        }
+        self.length = self.length.wrapping_add(data.len() as u32);
+    }
+
+    pub fn digest(&self) -> u32 {
+        !self.crc
+    }
+
+    // The length of the data modulo 2^32, as in gzip trailers.
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    pub fn new() -> Self {
+        Adler32 { a: 1, b: 0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for value in data {
+            self.a = (self.a + *value as u32) % 65521;
+            self.b = (self.b + self.a) % 65521;
+        }
+    }
+
+    pub fn digest(&self) -> u32 {
+        self.b << 16 | self.a
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Adler32::new()
+    }
+}
+
+#[test]
+fn test_checksums() {
+    let mut crc = Crc32::new();
+    assert_eq!(crc.digest(), 0);
+    crc.update(b"123456");
+    crc.update(b"789");
+    assert_eq!(crc.digest(), 0xcbf43926);
+    assert_eq!(crc.length(), 9);
+
+    let mut adler = Adler32::new();
+    assert_eq!(adler.digest(), 1);
+    adler.update(b"Wikipedia");
+    assert_eq!(adler.digest(), 0x11e60398);
+}
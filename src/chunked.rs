@@ -0,0 +1,222 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Support for the chunked transfer coding.
+
+use core::cmp;
+
+use traits::{Channel, ChannelError};
+
+/// Decodes a chunked body, reporting the end of the body as the end of the stream.
+pub struct ChunkedReader<'a, T: 'a> {
+    channel: &'a mut T,
+    // Bytes left to read in the current chunk.
+    remaining: usize,
+    started: bool,
+    done: bool,
+}
+
+impl<'a, T: Channel> ChunkedReader<'a, T> {
+    pub fn new(channel: &'a mut T) -> Self {
+        ChunkedReader {
+            channel: channel,
+            remaining: 0,
+            started: false,
+            done: false,
+        }
+    }
+
+    // Reads the size of the next chunk, and the trailer if this is the last one.
+    fn next_chunk(&mut self) -> Result<(), ChannelError> {
+        let mut buffer = [0u8; 256];
+
+        // Each chunk's data is followed by a line end.
+        if self.started && !self.channel.read_string_until(&mut buffer, "\r\n")?.is_empty() {
+            return Err(ChannelError::InvalidString);
+        }
+        self.started = true;
+
+        let size = parse_chunk_size(self.channel.read_string_until(&mut buffer, "\r\n")?)?;
+        if size == 0 {
+            // Skip the trailer fields, up to the final empty line.
+            while !self.channel.read_string_until(&mut buffer, "\r\n")?.is_empty() {}
+            self.done = true;
+        }
+        self.remaining = size;
+        Ok(())
+    }
+}
+
+/// A body read as is, or decoded from the chunked transfer coding.
+pub enum Body<'a, T: 'a> {
+    Identity(&'a mut T),
+    Chunked(ChunkedReader<'a, T>),
+}
+
+impl<'a, T: Channel> Body<'a, T> {
+    pub fn new(channel: &'a mut T, chunked: bool) -> Self {
+        if chunked {
+            Body::Chunked(ChunkedReader::new(channel))
+        } else {
+            Body::Identity(channel)
+        }
+    }
+}
+
+impl<'a, T: Channel> Channel for Body<'a, T> {
+    fn open(&mut self, host: &str, port: u16, tls: bool) -> Result<(), ChannelError> {
+        match *self {
+            Body::Identity(ref mut channel) => channel.open(host, port, tls),
+            Body::Chunked(ref mut reader) => reader.open(host, port, tls),
+        }
+    }
+
+    fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+        match *self {
+            Body::Identity(ref mut channel) => channel.send(data, len),
+            Body::Chunked(ref mut reader) => reader.send(data, len),
+        }
+    }
+
+    fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+        match *self {
+            Body::Identity(ref mut channel) => channel.recv(data, max_len),
+            Body::Chunked(ref mut reader) => reader.recv(data, max_len),
+        }
+    }
+}
+
+// Sends data as a single chunk. An empty chunk would end the body, so it is skipped.
+pub fn write_chunk<T: Channel>(channel: &mut T, data: &[u8]) -> Result<(), ChannelError> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    channel.send_all(format!("{:x}\r\n", data.len()).as_bytes())?;
+    channel.send_all(data)?;
+    channel.send_all(b"\r\n")
+}
+
+/// Sends the data written to it as chunks, eg. for the output of an encoder.
+pub struct ChunkedWriter<'a, T: 'a> {
+    channel: &'a mut T,
+}
+
+impl<'a, T: Channel> ChunkedWriter<'a, T> {
+    pub fn new(channel: &'a mut T) -> Self {
+        ChunkedWriter { channel: channel }
+    }
+}
+
+impl<'a, T: Channel> Channel for ChunkedWriter<'a, T> {
+    fn open(&mut self, host: &str, port: u16, tls: bool) -> Result<(), ChannelError> {
+        self.channel.open(host, port, tls)
+    }
+
+    fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+        write_chunk(self.channel, &data[..len])?;
+        Ok(len)
+    }
+
+    fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+        self.channel.recv(data, max_len)
+    }
+}
+
+// Ends a chunked body, without trailer fields.
+pub fn write_last_chunk<T: Channel>(channel: &mut T) -> Result<(), ChannelError> {
+    channel.send_all(b"0\r\n\r\n")
+}
+
+// Parses a chunk size line, ignoring chunk extensions.
+fn parse_chunk_size(line: &str) -> Result<usize, ChannelError> {
+    let size = line.split(';').next().unwrap_or("").trim();
+    if size.is_empty() || !size.bytes().all(|value| value.is_ascii_hexdigit()) {
+        return Err(ChannelError::InvalidString);
+    }
+    usize::from_str_radix(size, 16).map_err(|_| ChannelError::InvalidString)
+}
+
+// The stream ending before the last chunk is an invalid body, not the end of the body.
+fn truncated(err: ChannelError) -> ChannelError {
+    match err {
+        ChannelError::EndOfStream => ChannelError::InvalidString,
+        err => err,
+    }
+}
+
+impl<'a, T: Channel> Channel for ChunkedReader<'a, T> {
+    fn open(&mut self, host: &str, port: u16, tls: bool) -> Result<(), ChannelError> {
+        self.channel.open(host, port, tls)
+    }
+
+    fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+        self.channel.send(data, len)
+    }
+
+    fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+        if max_len == 0 {
+            return Ok(0);
+        }
+
+        while self.remaining == 0 {
+            if self.done {
+                return Err(ChannelError::EndOfStream);
+            }
+            self.next_chunk().map_err(truncated)?;
+        }
+
+        let len = cmp::min(max_len, self.remaining);
+        let size = self.channel.recv(data, len).map_err(truncated)?;
+        if size == 0 {
+            return Err(ChannelError::InvalidString);
+        }
+        self.remaining -= size;
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use traits::StringChannel;
+
+    #[test]
+    fn test_chunked_reader() {
+        let mut channel = StringChannel::new("5\r\nHello\r\n7;name=value\r\n, World\r\n0\r\nX-Trailer: \
+                                              1\r\n\r\nNext");
+        {
+            let mut reader = ChunkedReader::new(&mut channel);
+            let mut buffer = [0u8; 64];
+            assert_eq!(reader.read_string_to_end(&mut buffer).unwrap(), "Hello, World");
+            let mut buffer = [0u8; 1];
+            assert_eq!(reader.recv(&mut buffer, 1).err().unwrap(),
+                       ChannelError::EndOfStream);
+        }
+
+        // We didn't read past the end of the body.
+        let mut buffer = [0u8; 64];
+        assert_eq!(channel.read_string_to_end(&mut buffer).unwrap(), "Next");
+    }
+
+    #[test]
+    fn test_chunked_reader_errors() {
+        let mut buffer = [0u8; 64];
+
+        let mut channel = StringChannel::new("5\r\nHelloX\r\n0\r\n\r\n");
+        assert_eq!(ChunkedReader::new(&mut channel).read_string_to_end(&mut buffer).err().unwrap(),
+                   ChannelError::InvalidString);
+
+        let mut channel = StringChannel::new("zz\r\n");
+        assert_eq!(ChunkedReader::new(&mut channel).read_string_to_end(&mut buffer).err().unwrap(),
+                   ChannelError::InvalidString);
+
+        // Truncated bodies.
+        for body in &["5\r\nHel", "5\r\nHello\r\n", "5\r\nHello\r\n0\r\n"] {
+            let mut channel = StringChannel::new(body);
+            let mut reader = ChunkedReader::new(&mut channel);
+            assert_eq!(reader.read_string_to_end(&mut buffer).err().unwrap(),
+                       ChannelError::InvalidString);
+        }
+    }
+}
@@ -0,0 +1,604 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Set-Cookie parsing and a bounded cookie store (RFC 6265).
+
+use collections::{String, Vec};
+use core::str::{self, FromStr};
+
+use date::{days_from_civil, MONTHS};
+
+// Larger Set-Cookie headers are ignored.
+pub const MAX_COOKIE_SIZE: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    fn from_token(value: &str) -> Option<SameSite> {
+        if value.eq_ignore_ascii_case("Strict") {
+            Some(SameSite::Strict)
+        } else if value.eq_ignore_ascii_case("Lax") {
+            Some(SameSite::Lax)
+        } else if value.eq_ignore_ascii_case("None") {
+            Some(SameSite::None)
+        } else {
+            None
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match *self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    // Lowercase, without a leading dot.
+    pub domain: String,
+    // Without a Domain attribute, the cookie is only sent to the host that set it.
+    pub host_only: bool,
+    pub path: String,
+    // In seconds since the Unix epoch. Session cookies have no expiry time.
+    pub expires: Option<u64>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+    // Sequence numbers of the jar, to order and evict cookies without a clock.
+    creation: u64,
+    last_access: u64,
+}
+
+impl Cookie {
+    // Parses a Set-Cookie header value received from `host` for a request to `path`. Returns
+    // None if the cookie must be ignored, eg. when its Domain doesn't match the host.
+    pub fn parse(set_cookie: &str, host: &str, path: &str, now: u64) -> Option<Cookie> {
+        let mut attributes = set_cookie.split(';');
+        let (name, value) = split_pair(attributes.next()?);
+        if name.is_empty() || value.is_none() || has_ctl(name) || has_ctl(value?) {
+            return None;
+        }
+
+        let host = host.to_ascii_lowercase();
+        let mut cookie = Cookie {
+            name: String::from(name),
+            value: String::from(value?),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(path),
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+            creation: 0,
+            last_access: 0,
+        };
+
+        let mut max_age = None;
+        let mut expires = None;
+        for attribute in attributes {
+            let (name, value) = split_pair(attribute);
+            let value = value.unwrap_or("");
+            if name.eq_ignore_ascii_case("Expires") {
+                expires = parse_cookie_date(value).or(expires);
+            } else if name.eq_ignore_ascii_case("Max-Age") {
+                max_age = parse_max_age(value).or(max_age);
+            } else if name.eq_ignore_ascii_case("Domain") && !value.is_empty() {
+                // A domain without dots would be a top-level one, and IP addresses have no
+                // subdomains.
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                if !domain.contains('.') || is_ip_address(&domain) ||
+                   !domain_match(&host, &domain) {
+                    return None;
+                }
+                cookie.domain = domain;
+                cookie.host_only = false;
+            } else if name.eq_ignore_ascii_case("Path") {
+                cookie.path = if value.starts_with('/') && !has_ctl(value) {
+                    String::from(value)
+                } else {
+                    default_path(path)
+                };
+            } else if name.eq_ignore_ascii_case("Secure") {
+                cookie.secure = true;
+            } else if name.eq_ignore_ascii_case("HttpOnly") {
+                cookie.http_only = true;
+            } else if name.eq_ignore_ascii_case("SameSite") {
+                cookie.same_site = SameSite::from_token(value);
+            }
+        }
+
+        // Max-Age has precedence, and a zero or negative one deletes the cookie.
+        cookie.expires = match max_age {
+            Some(max_age) if max_age <= 0 => Some(0),
+            Some(max_age) => Some(now.saturating_add(max_age as u64)),
+            None => expires,
+        };
+        Some(cookie)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.map_or(false, |expires| expires <= now)
+    }
+
+    // Whether this cookie is sent with a request to this host and path.
+    pub fn matches(&self, secure: bool, host: &str, path: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(&host, &self.domain)
+        };
+        domain_ok && path_match(request_path(path), &self.path) && (secure || !self.secure)
+    }
+}
+
+// Splits `name=value`, trimming both. The value is None without an `=`.
+fn split_pair(pair: &str) -> (&str, Option<&str>) {
+    match pair.find('=') {
+        Some(pos) => (pair[..pos].trim(), Some(pair[pos + 1..].trim())),
+        None => (pair.trim(), None),
+    }
+}
+
+fn has_ctl(value: &str) -> bool {
+    value.bytes().any(|byte| byte < 0x20 || byte == 0x7f)
+}
+
+fn parse_max_age(value: &str) -> Option<i64> {
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    // Saturate huge values instead of ignoring them.
+    Some(i64::from_str(value).unwrap_or(if digits.len() == value.len() {
+        i64::max_value()
+    } else {
+        i64::min_value()
+    }))
+}
+
+// Strips the query from a request path.
+fn request_path(path: &str) -> &str {
+    path.split('?').next().unwrap_or("/")
+}
+
+// The directory of the request path, used without a Path attribute.
+fn default_path(path: &str) -> String {
+    let path = request_path(path);
+    match path.rfind('/') {
+        Some(pos) if pos > 0 && path.starts_with('/') => String::from(&path[..pos]),
+        _ => String::from("/"),
+    }
+}
+
+fn is_ip_address(host: &str) -> bool {
+    host.contains(':') || host.bytes().all(|byte| byte.is_ascii_digit() || byte == b'.')
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    // Only host names have subdomains.
+    !is_ip_address(host) && !domain.is_empty() && host.ends_with(domain) &&
+    host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+}
+
+fn path_match(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path ||
+    (path.starts_with(cookie_path) &&
+     (cookie_path.ends_with('/') || path.as_bytes()[cookie_path.len()] == b'/'))
+}
+
+fn is_delimiter(value: u8) -> bool {
+    match value {
+        0x09 | 0x20..=0x2f | 0x3b..=0x40 | 0x5b..=0x60 | 0x7b..=0x7e => true,
+        _ => false,
+    }
+}
+
+// Parses one to `max` leading digits, that must not be followed by another digit.
+fn leading_number(token: &str, max: usize) -> Option<(u32, &str)> {
+    let count = token.bytes().take_while(|byte| byte.is_ascii_digit()).count();
+    if count == 0 || count > max {
+        return None;
+    }
+    Some((u32::from_str(&token[..count]).ok()?, &token[count..]))
+}
+
+fn parse_time(token: &str) -> Option<(u32, u32, u32)> {
+    let (hour, rest) = leading_number(token, 2)?;
+    let (minute, rest) = leading_number(rest.strip_prefix(':')?, 2)?;
+    let (second, _) = leading_number(rest.strip_prefix(':')?, 2)?;
+    Some((hour, minute, second))
+}
+
+// Parses the lenient date format of cookies (RFC 6265, section 5.1.1), into seconds since the
+// Unix epoch. Earlier dates are clamped to the epoch.
+pub fn parse_cookie_date(value: &str) -> Option<u64> {
+    let mut time = None;
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+    for token in value.split(|c: char| c.is_ascii() && is_delimiter(c as u8)) {
+        if token.is_empty() {
+            continue;
+        }
+        if time.is_none() {
+            if let Some(value) = parse_time(token) {
+                time = Some(value);
+                continue;
+            }
+        }
+        if day.is_none() {
+            if let Some((value, _)) = leading_number(token, 2) {
+                day = Some(value);
+                continue;
+            }
+        }
+        if month.is_none() && token.len() >= 3 {
+            let prefix = token.get(..3).unwrap_or("");
+            if let Some(pos) = MONTHS.iter().position(|month| month.eq_ignore_ascii_case(prefix)) {
+                month = Some(pos as u32 + 1);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some((value, _)) = leading_number(token, 4) {
+                if token.bytes().take_while(|byte| byte.is_ascii_digit()).count() >= 2 {
+                    year = Some(value);
+                    continue;
+                }
+            }
+        }
+    }
+
+    let (hour, minute, second) = time?;
+    let mut year = year?;
+    if year < 70 {
+        year += 2000;
+    } else if year < 100 {
+        year += 1900;
+    }
+    let (day, month) = (day?, month?);
+    if day < 1 || day > 31 || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    let seconds = days_from_civil(year as i64, month, day) * 86400 +
+                  (hour * 3600 + minute * 60 + second) as i64;
+    Some(if seconds < 0 { 0 } else { seconds as u64 })
+}
+
+/// Stores at most a fixed number of cookies, evicting the least recently used ones.
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+    max_cookies: usize,
+    sequence: u64,
+}
+
+impl CookieJar {
+    pub fn new(max_cookies: usize) -> Self {
+        CookieJar {
+            cookies: Vec::new(),
+            max_cookies: max_cookies,
+            sequence: 0,
+        }
+    }
+
+    pub fn cookies(&self) -> &[Cookie] {
+        &self.cookies
+    }
+
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+
+    fn remove_expired(&mut self, now: u64) {
+        self.cookies.retain(|cookie| !cookie.is_expired(now));
+    }
+
+    // Stores a cookie, replacing the one with the same name, domain and path. An expired
+    // cookie only removes the one it replaces.
+    pub fn insert(&mut self, mut cookie: Cookie, now: u64) {
+        self.sequence += 1;
+        cookie.creation = self.sequence;
+        cookie.last_access = self.sequence;
+        let existing = self.cookies.iter().position(|other| {
+            other.name == cookie.name && other.domain == cookie.domain && other.path == cookie.path
+        });
+        if let Some(pos) = existing {
+            cookie.creation = self.cookies.remove(pos).creation;
+        }
+
+        self.remove_expired(now);
+        if cookie.is_expired(now) || self.max_cookies == 0 {
+            return;
+        }
+        if self.cookies.len() >= self.max_cookies {
+            let oldest = self.cookies
+                .iter()
+                .enumerate()
+                .min_by_key(|cookie| cookie.1.last_access)
+                .map(|cookie| cookie.0)
+                .unwrap();
+            self.cookies.remove(oldest);
+        }
+        self.cookies.push(cookie);
+    }
+
+    // Stores the cookie of a Set-Cookie header received from `host`, over https if `secure`
+    // is set, for a request to `path`.
+    pub fn set_cookie(&mut self, set_cookie: &str, secure: bool, host: &str, path: &str, now: u64) {
+        if set_cookie.len() > MAX_COOKIE_SIZE {
+            return;
+        }
+        if let Some(cookie) = Cookie::parse(set_cookie, host, path, now) {
+            // Only secure origins can set secure cookies.
+            if secure || !cookie.secure {
+                self.insert(cookie, now);
+            }
+        }
+    }
+
+    // The value of the Cookie header for a request, or None if no cookie matches. Cookies with
+    // longer paths come first.
+    pub fn cookie_header(&mut self,
+                         secure: bool,
+                         host: &str,
+                         path: &str,
+                         now: u64)
+                         -> Option<String> {
+        self.remove_expired(now);
+        self.sequence += 1;
+        let sequence = self.sequence;
+
+        let mut cookies: Vec<&mut Cookie> = self.cookies
+            .iter_mut()
+            .filter(|cookie| cookie.matches(secure, host, path))
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+        cookies.sort_by(|a, b| {
+            b.path.len().cmp(&a.path.len()).then(a.creation.cmp(&b.creation))
+        });
+
+        let mut res = String::new();
+        for cookie in cookies {
+            cookie.last_access = sequence;
+            if !res.is_empty() {
+                res.push_str("; ");
+            }
+            res.push_str(&cookie.name);
+            res.push('=');
+            res.push_str(&cookie.value);
+        }
+        Some(res)
+    }
+
+    // Serializes the persistent cookies, one per line with tab separated fields, to be
+    // stored eg. in flash. Session cookies are not saved.
+    pub fn save(&self) -> Vec<u8> {
+        let mut cookies: Vec<&Cookie> = self.cookies
+            .iter()
+            .filter(|cookie| cookie.expires.is_some())
+            .collect();
+        cookies.sort_by_key(|cookie| cookie.creation);
+
+        let mut res = String::new();
+        for cookie in cookies {
+            res.push_str(&format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                                  cookie.domain,
+                                  cookie.host_only as u8,
+                                  cookie.path,
+                                  cookie.secure as u8,
+                                  cookie.http_only as u8,
+                                  cookie.same_site.map_or("", |same_site| same_site.as_str()),
+                                  cookie.expires.unwrap_or(0),
+                                  cookie.name,
+                                  cookie.value));
+        }
+        res.into_bytes()
+    }
+
+    // Adds the cookies saved by save(), skipping the expired and invalid ones. Returns the
+    // number of cookies added.
+    pub fn load(&mut self, data: &[u8], now: u64) -> usize {
+        let data = match str::from_utf8(data) {
+            Ok(data) => data,
+            Err(_) => return 0,
+        };
+
+        let mut count = 0;
+        for line in data.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 9 || fields[7].is_empty() {
+                continue;
+            }
+            let flag = |field: &str| match field {
+                "0" => Some(false),
+                "1" => Some(true),
+                _ => None,
+            };
+            let (host_only, secure, http_only) = match (flag(fields[1]),
+                                                        flag(fields[3]),
+                                                        flag(fields[4])) {
+                (Some(host_only), Some(secure), Some(http_only)) => (host_only, secure, http_only),
+                _ => continue,
+            };
+            let expires = match u64::from_str(fields[6]) {
+                Ok(expires) if expires > now => expires,
+                _ => continue,
+            };
+
+            self.insert(Cookie {
+                            name: String::from(fields[7]),
+                            value: String::from(fields[8]),
+                            domain: String::from(fields[0]),
+                            host_only: host_only,
+                            path: String::from(fields[2]),
+                            expires: Some(expires),
+                            secure: secure,
+                            http_only: http_only,
+                            same_site: SameSite::from_token(fields[5]),
+                            creation: 0,
+                            last_access: 0,
+                        },
+                        now);
+            count += 1;
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Sun, 06 Nov 1994 08:49:37 GMT
+    const DATE: u64 = 784111777;
+
+    #[test]
+    fn test_cookie_date() {
+        assert_eq!(parse_cookie_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(DATE));
+        assert_eq!(parse_cookie_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(DATE));
+        assert_eq!(parse_cookie_date("Sun Nov  6 08:49:37 1994"), Some(DATE));
+        assert_eq!(parse_cookie_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_cookie_date("Wed, 01 Jan 1969 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_cookie_date("Tue, 29 Feb 2028 23:59:59 GMT"), Some(1835481599));
+        assert_eq!(parse_cookie_date("Sun, 06 Nov 1994"), None);
+        assert_eq!(parse_cookie_date("Sun, 32 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_cookie_date("Sun, 06 Nov 1994 24:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let cookie = Cookie::parse("SID=31d4d96e407aad42; Path=/; Domain=.Example.com; Secure; \
+                                    HttpOnly; SameSite=Lax; Max-Age=60; Expires=Sun, 06 Nov 1994 \
+                                    08:49:37 GMT",
+                                   "www.example.com",
+                                   "/login",
+                                   1000)
+            .unwrap();
+        assert_eq!(cookie.name, "SID");
+        assert_eq!(cookie.value, "31d4d96e407aad42");
+        assert_eq!(cookie.domain, "example.com");
+        assert!(!cookie.host_only);
+        assert_eq!(cookie.path, "/");
+        assert_eq!(cookie.expires, Some(1060));
+        assert!(cookie.secure);
+        assert!(cookie.http_only);
+        assert_eq!(cookie.same_site, Some(SameSite::Lax));
+
+        let cookie = Cookie::parse("lang=en-US; Expires=Sun, 06 Nov 1994 08:49:37 GMT",
+                                   "Example.com",
+                                   "/docs/web/index.html?x=/a",
+                                   0)
+            .unwrap();
+        assert_eq!(cookie.domain, "example.com");
+        assert!(cookie.host_only);
+        assert_eq!(cookie.path, "/docs/web");
+        assert_eq!(cookie.expires, Some(DATE));
+        assert!(!cookie.secure);
+
+        assert_eq!(Cookie::parse("a=b; Max-Age=-1", "example.com", "/", 1000).unwrap().expires,
+                   Some(0));
+        assert_eq!(Cookie::parse("a=b; Max-Age=x", "example.com", "/", 1000).unwrap().expires,
+                   None);
+        assert_eq!(Cookie::parse("a=b; Domain=other.com", "example.com", "/", 0), None);
+        assert_eq!(Cookie::parse("a=b; Domain=0.1", "192.168.0.1", "/", 0), None);
+        assert_eq!(Cookie::parse("a=b; Domain=192.168.0.1", "192.168.0.1", "/", 0), None);
+        assert_eq!(Cookie::parse("a=b; Domain=[::1]", "[::1]", "/", 0), None);
+        assert_eq!(Cookie::parse("a=b; Domain=com", "example.com", "/", 0), None);
+        assert_eq!(Cookie::parse("a=b; Domain=.local", "printer.local", "/", 0), None);
+        assert_eq!(Cookie::parse("=b", "example.com", "/", 0), None);
+        assert_eq!(Cookie::parse("a", "example.com", "/", 0), None);
+        assert!(Cookie::parse("a=", "example.com", "/", 0).is_some());
+    }
+
+    #[test]
+    fn test_matches() {
+        let cookie = Cookie::parse("a=b; Domain=example.com; Path=/docs", "example.com", "/", 0)
+            .unwrap();
+        assert!(cookie.matches(false, "example.com", "/docs"));
+        assert!(cookie.matches(false, "www.Example.com", "/docs/web?q=1"));
+        assert!(!cookie.matches(false, "badexample.com", "/docs"));
+        assert!(!cookie.matches(false, "example.com", "/docsets"));
+        assert!(!cookie.matches(false, "example.com", "/"));
+
+        let cookie = Cookie::parse("a=b; Secure", "example.com", "/", 0).unwrap();
+        assert!(cookie.matches(true, "example.com", "/"));
+        assert!(!cookie.matches(false, "example.com", "/"));
+        assert!(!cookie.matches(true, "www.example.com", "/"));
+    }
+
+    #[test]
+    fn test_jar() {
+        let mut jar = CookieJar::new(3);
+        jar.set_cookie("a=1; Path=/", false, "example.com", "/", 0);
+        jar.set_cookie("b=2; Path=/admin", false, "example.com", "/", 0);
+        jar.set_cookie("c=3; Max-Age=10", false, "example.com", "/", 0);
+        jar.set_cookie("d=4; Secure", false, "example.com", "/", 0);
+        assert_eq!(jar.cookie_header(false, "example.com", "/admin/status", 5),
+                   Some(String::from("b=2; a=1; c=3")));
+        assert_eq!(jar.cookie_header(false, "example.com", "/", 10),
+                   Some(String::from("a=1")));
+        assert_eq!(jar.cookie_header(false, "other.com", "/", 10), None);
+
+        // Replacing keeps the position, and deleting frees space.
+        jar.set_cookie("b=5; Path=/admin", false, "example.com", "/", 10);
+        jar.set_cookie("a=6; Max-Age=0", false, "example.com", "/", 10);
+        assert_eq!(jar.cookie_header(false, "example.com", "/admin", 10),
+                   Some(String::from("b=5")));
+
+        // The least recently used cookie is evicted.
+        jar.set_cookie("e=7", false, "example.com", "/", 10);
+        jar.set_cookie("f=8", false, "example.com", "/", 10);
+        jar.cookie_header(false, "example.com", "/", 10);
+        jar.set_cookie("g=9", false, "example.com", "/", 10);
+        assert_eq!(jar.cookies().len(), 3);
+        assert_eq!(jar.cookie_header(false, "example.com", "/admin", 10),
+                   Some(String::from("e=7; f=8; g=9")));
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut jar = CookieJar::new(10);
+        jar.set_cookie("session=1", true, "example.com", "/", 0);
+        jar.set_cookie("a=1; Max-Age=100; Secure; HttpOnly; SameSite=Strict",
+                       true,
+                       "example.com",
+                       "/",
+                       0);
+        jar.set_cookie("b=2; Domain=example.com; Path=/x; Max-Age=10",
+                       true,
+                       "example.com",
+                       "/",
+                       0);
+        let saved = jar.save();
+        assert_eq!(str::from_utf8(&saved).unwrap(),
+                   "example.com\t1\t/\t1\t1\tStrict\t100\ta\t1\n\
+                    example.com\t0\t/x\t0\t0\t\t10\tb\t2\n");
+
+        let mut jar = CookieJar::new(10);
+        assert_eq!(jar.load(&saved, 50), 1);
+        assert_eq!(jar.load(b"garbage\n", 50), 0);
+        assert_eq!(jar.cookies()[0].name, "a");
+        assert_eq!(jar.cookies()[0].same_site, Some(SameSite::Strict));
+        assert_eq!(jar.cookie_header(true, "example.com", "/x", 50),
+                   Some(String::from("a=1")));
+    }
+}
@@ -0,0 +1,181 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// HTTP dates (RFC 7231, section 7.1.1.1): the IMF-fixdate format, and the obsolete RFC 850
+// and asctime ones that recipients must still accept. Times are seconds since the Unix epoch.
+
+use collections::String;
+use core::str::FromStr;
+
+static DAYS: [&'static str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+static LONG_DAYS: [&'static str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday",
+                                       "Friday", "Saturday"];
+pub static MONTHS: [&'static str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug",
+                                     "Sep", "Oct", "Nov", "Dec"];
+
+// Days between the Unix epoch and a date of the proleptic Gregorian calendar.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// The date of a number of days since the Unix epoch, as year, month and day.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 -
+                       day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// Parses a number made of exactly `len` digits.
+fn parse_digits(value: &str, len: usize) -> Option<u32> {
+    if value.len() != len || !value.bytes().all(|value| value.is_ascii_digit()) {
+        return None;
+    }
+    u32::from_str(value).ok()
+}
+
+fn parse_month(value: &str) -> Option<u32> {
+    MONTHS.iter().position(|month| *month == value).map(|pos| pos as u32 + 1)
+}
+
+// Parses a `hh:mm:ss` time into seconds.
+fn parse_time(value: &str) -> Option<u32> {
+    let mut parts = value.split(':');
+    let hour = parse_digits(parts.next()?, 2)?;
+    let minute = parse_digits(parts.next()?, 2)?;
+    let second = parse_digits(parts.next()?, 2)?;
+    // Leap seconds are allowed.
+    if parts.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    Some(hour * 3600 + minute * 60 + second)
+}
+
+fn to_seconds(year: u32, month: u32, day: u32, time: u32) -> Option<u64> {
+    let days_in_month = match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if year < 1970 || day < 1 || day > days_in_month {
+        return None;
+    }
+    Some(days_from_civil(year as i64, month, day) as u64 * 86400 + time as u64)
+}
+
+// Parses a date in any of the three HTTP formats:
+//
+//   Sun, 06 Nov 1994 08:49:37 GMT    IMF-fixdate
+//   Sunday, 06-Nov-94 08:49:37 GMT   RFC 850
+//   Sun Nov  6 08:49:37 1994         asctime
+//
+// Dates before the Unix epoch are rejected.
+pub fn parse_http_date(value: &str) -> Option<u64> {
+    let mut tokens = value.split_whitespace();
+    let day_name = tokens.next()?;
+    let (year, month, day, time) = if let Some(day_name) = day_name.strip_suffix(',') {
+        let date = tokens.next()?;
+        if DAYS.contains(&day_name) {
+            let day = parse_digits(date, 2)?;
+            let month = parse_month(tokens.next()?)?;
+            let year = parse_digits(tokens.next()?, 4)?;
+            (year, month, day, parse_time(tokens.next()?)?)
+        } else if LONG_DAYS.contains(&day_name) {
+            let mut parts = date.split('-');
+            let day = parse_digits(parts.next()?, 2)?;
+            let month = parse_month(parts.next()?)?;
+            let year = parse_digits(parts.next()?, 2)?;
+            if parts.next().is_some() {
+                return None;
+            }
+            // Two digit years before 70 are in the 21st century.
+            let year = if year < 70 { year + 2000 } else { year + 1900 };
+            (year, month, day, parse_time(tokens.next()?)?)
+        } else {
+            return None;
+        }
+    } else {
+        if !DAYS.contains(&day_name) {
+            return None;
+        }
+        let month = parse_month(tokens.next()?)?;
+        let day = tokens.next()?;
+        let day = parse_digits(day, day.len()).filter(|_| day.len() <= 2)?;
+        let time = parse_time(tokens.next()?)?;
+        let year = parse_digits(tokens.next()?, 4)?;
+        if tokens.next().is_some() {
+            return None;
+        }
+        return to_seconds(year, month, day, time);
+    };
+    if tokens.next() != Some("GMT") || tokens.next().is_some() {
+        return None;
+    }
+    to_seconds(year, month, day, time)
+}
+
+// Formats a time as an IMF-fixdate, eg. for If-Modified-Since headers.
+pub fn format_http_date(seconds: u64) -> String {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+    let (year, month, day) = civil_from_days(days);
+    // The Unix epoch was a Thursday.
+    let day_name = DAYS[((days + 4) % 7) as usize];
+    format!("{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+            day_name,
+            day,
+            MONTHS[month as usize - 1],
+            year,
+            time / 3600,
+            time / 60 % 60,
+            time % 60)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_http_date() {
+        let expected = Some(784111777);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), expected);
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_http_date("Tuesday, 29-Feb-28 23:59:59 GMT"), Some(1835481599));
+
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 UTC"), None);
+        assert_eq!(parse_http_date("Sun, 6 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 31 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 24:00:00 GMT"), None);
+        assert_eq!(parse_http_date("Sunday, 06 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun Nov 6 08:49:37 1994 GMT"), None);
+        assert_eq!(parse_http_date("Wed, 31 Dec 1969 23:59:59 GMT"), None);
+        assert_eq!(parse_http_date(""), None);
+    }
+
+    #[test]
+    fn test_format_http_date() {
+        assert_eq!(format_http_date(784111777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(format_http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(format_http_date(951782400), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(civil_from_days(days_from_civil(2400, 12, 31)), (2400, 12, 31));
+        let time = 4102444799;
+        assert_eq!(parse_http_date(&format_http_date(time)), Some(time));
+    }
+}
@@ -0,0 +1,384 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// A streaming deflate encoder (RFC 1951), producing the gzip and zlib formats.
+//
+// Matches are found with a single entry hash table and encoded with the fixed Huffman codes,
+// which needs no buffering of the output: the memory used is the window buffer provided by
+// the caller, and about 1 KiB more.
+
+use core::cmp;
+
+use checksum::{Adler32, Crc32};
+use inflate::{Format, DISTANCE_BASE, DISTANCE_EXTRA, LENGTH_BASE, LENGTH_EXTRA};
+use traits::{Channel, ChannelError};
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 9;
+// An empty entry of the hash table.
+const NONE: u16 = 0xffff;
+
+/// Compresses data written in pieces, sending the output to a channel.
+pub struct Deflater<'w> {
+    format: Format,
+    // Positions before `start` are the history, the data from `start` to `len` is waiting for
+    // compression.
+    window: &'w mut [u8],
+    start: usize,
+    len: usize,
+    // The last position of each hash of 3 bytes.
+    head: [u16; 1 << HASH_BITS],
+    started: bool,
+    bit_buffer: u32,
+    bit_count: u32,
+    out: [u8; 128],
+    out_len: usize,
+    crc: Crc32,
+    adler: Adler32,
+}
+
+impl<'w> Deflater<'w> {
+    // Back references go as far as half of the window, that must be between 1 and 64 KiB.
+    // Larger windows compress better.
+    pub fn new(format: Format, window: &'w mut [u8]) -> Self {
+        assert!(window.len() >= 1024);
+        let size = cmp::min(window.len(), 0xffff);
+        Deflater {
+            format: format,
+            window: &mut window[..size],
+            start: 0,
+            len: 0,
+            head: [NONE; 1 << HASH_BITS],
+            started: false,
+            bit_buffer: 0,
+            bit_count: 0,
+            out: [0; 128],
+            out_len: 0,
+            crc: Crc32::new(),
+            adler: Adler32::new(),
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    // The value of the Content-Encoding header.
+    pub fn content_encoding(&self) -> &'static str {
+        match self.format {
+            Format::Gzip => "gzip",
+            Format::Deflate => "deflate",
+        }
+    }
+
+    // Prepares the encoder for new data, dropping the current state.
+    pub fn reset(&mut self) {
+        self.start = 0;
+        self.len = 0;
+        self.head = [NONE; 1 << HASH_BITS];
+        self.started = false;
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+        self.out_len = 0;
+        self.crc = Crc32::new();
+        self.adler = Adler32::new();
+    }
+
+    fn flush_output<T: Channel>(&mut self, channel: &mut T) -> Result<(), ChannelError> {
+        if self.out_len != 0 {
+            channel.send_all(&self.out[..self.out_len])?;
+            self.out_len = 0;
+        }
+        Ok(())
+    }
+
+    fn put_byte<T: Channel>(&mut self, channel: &mut T, value: u8) -> Result<(), ChannelError> {
+        self.out[self.out_len] = value;
+        self.out_len += 1;
+        if self.out_len == self.out.len() {
+            self.flush_output(channel)?;
+        }
+        Ok(())
+    }
+
+    // Writes `count` bits, least significant first.
+    fn put_bits<T: Channel>(&mut self,
+                            channel: &mut T,
+                            value: u32,
+                            count: u32)
+                            -> Result<(), ChannelError> {
+        self.bit_buffer |= value << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            let value = self.bit_buffer as u8;
+            self.put_byte(channel, value)?;
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+        Ok(())
+    }
+
+    // Huffman codes are written most significant bit first.
+    fn put_code<T: Channel>(&mut self,
+                            channel: &mut T,
+                            code: u32,
+                            length: u32)
+                            -> Result<(), ChannelError> {
+        let reversed = code.reverse_bits() >> (32 - length);
+        self.put_bits(channel, reversed, length)
+    }
+
+    // Writes a literal or length symbol with the fixed code.
+    fn put_symbol<T: Channel>(&mut self, channel: &mut T, symbol: u32) -> Result<(), ChannelError> {
+        match symbol {
+            0..=143 => self.put_code(channel, 0x30 + symbol, 8),
+            144..=255 => self.put_code(channel, 0x190 + symbol - 144, 9),
+            256..=279 => self.put_code(channel, symbol - 256, 7),
+            _ => self.put_code(channel, 0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn put_match<T: Channel>(&mut self,
+                             channel: &mut T,
+                             length: usize,
+                             distance: usize)
+                             -> Result<(), ChannelError> {
+        let index = LENGTH_BASE.iter().rposition(|base| *base as usize <= length).unwrap();
+        self.put_symbol(channel, 257 + index as u32)?;
+        self.put_bits(channel,
+                      (length - LENGTH_BASE[index] as usize) as u32,
+                      LENGTH_EXTRA[index] as u32)?;
+
+        let index = DISTANCE_BASE.iter().rposition(|base| *base as usize <= distance).unwrap();
+        self.put_code(channel, index as u32, 5)?;
+        self.put_bits(channel,
+                      (distance - DISTANCE_BASE[index] as usize) as u32,
+                      DISTANCE_EXTRA[index] as u32)
+    }
+
+    fn put_header<T: Channel>(&mut self, channel: &mut T) -> Result<(), ChannelError> {
+        self.started = true;
+        let header: &[u8] = match self.format {
+            // No file name nor modification time, and an unknown OS.
+            Format::Gzip => &[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff],
+            // A 32 KiB window at most, and the fastest compression level.
+            Format::Deflate => &[0x78, 0x01],
+        };
+        for value in header {
+            self.put_byte(channel, *value)?;
+        }
+        // All the data goes in a single block with fixed codes, ended by finish().
+        self.put_bits(channel, 0, 1)?;
+        self.put_bits(channel, 1, 2)
+    }
+
+    fn max_distance(&self) -> usize {
+        cmp::min(self.window.len() / 2, 32768)
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let value = (self.window[pos] as u32) << 16 | (self.window[pos + 1] as u32) << 8 |
+                    self.window[pos + 2] as u32;
+        (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    }
+
+    // Remembers the position for future matches, returning the previous one with this hash.
+    fn insert(&mut self, pos: usize) -> Option<usize> {
+        if pos + MIN_MATCH > self.len {
+            return None;
+        }
+        let hash = self.hash(pos);
+        let previous = self.head[hash];
+        self.head[hash] = pos as u16;
+        if previous == NONE {
+            None
+        } else {
+            Some(previous as usize)
+        }
+    }
+
+    // Compresses the pending data, keeping enough of it to find the longest matches unless
+    // `finish` is set.
+    fn compress<T: Channel>(&mut self, channel: &mut T, finish: bool) -> Result<(), ChannelError> {
+        while self.start < self.len && (finish || self.len - self.start >= MAX_MATCH) {
+            let pos = self.start;
+            let max_length = cmp::min(MAX_MATCH, self.len - pos);
+            let mut length = 0;
+            let mut distance = 0;
+            if let Some(candidate) = self.insert(pos) {
+                distance = pos - candidate;
+                if distance <= self.max_distance() {
+                    while length < max_length &&
+                          self.window[candidate + length] == self.window[pos + length] {
+                        length += 1;
+                    }
+                }
+            }
+
+            if length >= MIN_MATCH {
+                self.put_match(channel, length, distance)?;
+                for next in pos + 1..pos + length {
+                    self.insert(next);
+                }
+                self.start += length;
+            } else {
+                let value = self.window[pos] as u32;
+                self.put_symbol(channel, value)?;
+                self.start += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // Drops the history that is too old to be referenced, making room for new data.
+    fn slide(&mut self) {
+        let shift = self.start - cmp::min(self.start, self.max_distance());
+        if shift == 0 {
+            return;
+        }
+        self.window.copy_within(shift..self.len, 0);
+        self.start -= shift;
+        self.len -= shift;
+        for entry in self.head.iter_mut() {
+            *entry = if *entry == NONE || (*entry as usize) < shift {
+                NONE
+            } else {
+                *entry - shift as u16
+            };
+        }
+    }
+
+    // Compresses data, sending the output when enough of it is available.
+    pub fn write<T: Channel>(&mut self, channel: &mut T, data: &[u8]) -> Result<(), ChannelError> {
+        if !self.started {
+            self.put_header(channel)?;
+        }
+        match self.format {
+            Format::Gzip => self.crc.update(data),
+            Format::Deflate => self.adler.update(data),
+        }
+
+        let mut data = data;
+        while !data.is_empty() {
+            let size = cmp::min(data.len(), self.window.len() - self.len);
+            self.window[self.len..self.len + size].copy_from_slice(&data[..size]);
+            self.len += size;
+            data = &data[size..];
+            if self.len == self.window.len() {
+                self.compress(channel, false)?;
+                self.slide();
+            }
+        }
+        Ok(())
+    }
+
+    // Compresses the remaining data and sends the end of the stream. The encoder is then
+    // ready for new data.
+    pub fn finish<T: Channel>(&mut self, channel: &mut T) -> Result<(), ChannelError> {
+        if !self.started {
+            self.put_header(channel)?;
+        }
+        self.compress(channel, true)?;
+
+        // End the block, and add an empty final one.
+        self.put_symbol(channel, 256)?;
+        self.put_bits(channel, 1, 1)?;
+        self.put_bits(channel, 1, 2)?;
+        self.put_symbol(channel, 256)?;
+        if self.bit_count != 0 {
+            let count = 8 - self.bit_count;
+            self.put_bits(channel, 0, count)?;
+        }
+
+        let trailer = match self.format {
+            Format::Gzip => (self.crc.digest() as u64) | (self.crc.length() as u64) << 32,
+            Format::Deflate => self.adler.digest().swap_bytes() as u64,
+        };
+        let trailer_len = if self.format == Format::Gzip { 8 } else { 4 };
+        for value in trailer.to_le_bytes()[..trailer_len].iter() {
+            self.put_byte(channel, *value)?;
+        }
+        self.flush_output(channel)?;
+        self.reset();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use collections::{String, Vec};
+    use inflate::InflateReader;
+    use traits::MemoryChannel;
+
+    fn deflate(format: Format, window_size: usize, pieces: &[&[u8]]) -> Vec<u8> {
+        let mut window = vec![0u8; window_size];
+        let mut deflater = Deflater::new(format, &mut window);
+        let mut channel = MemoryChannel::new(b"");
+        for piece in pieces {
+            deflater.write(&mut channel, piece).unwrap();
+        }
+        deflater.finish(&mut channel).unwrap();
+        Vec::from(channel.sent())
+    }
+
+    fn inflate(format: Format, data: &[u8]) -> Vec<u8> {
+        let mut channel = MemoryChannel::new(data);
+        let mut window = vec![0u8; 32768];
+        let mut reader = InflateReader::new(&mut channel, format, &mut window);
+        let mut res = Vec::new();
+        let mut buffer = [0u8; 256];
+        loop {
+            let size = reader.read(&mut buffer).unwrap();
+            if size == 0 {
+                return res;
+            }
+            res.extend_from_slice(&buffer[..size]);
+        }
+    }
+
+    #[test]
+    fn test_deflate() {
+        let mut json = String::new();
+        for i in 0..500 {
+            json.push_str(&format!("{{\"sensor\": \"temperature\", \"value\": {}}}\n", i % 37));
+        }
+
+        for format in [Format::Gzip, Format::Deflate].iter() {
+            let compressed = deflate(*format, 1024, &[json.as_bytes()]);
+            assert!(compressed.len() < json.len() / 4);
+            assert_eq!(inflate(*format, &compressed), json.as_bytes());
+
+            // Small pieces and a larger window.
+            let pieces: Vec<&[u8]> = json.as_bytes().chunks(7).collect();
+            let compressed = deflate(*format, 4096, &pieces);
+            assert_eq!(inflate(*format, &compressed), json.as_bytes());
+        }
+
+        // Incompressible data and empty data.
+        let mut random = Vec::new();
+        let mut value: u32 = 1;
+        for _ in 0..5000 {
+            value = value.wrapping_mul(1103515245).wrapping_add(12345);
+            random.push((value >> 16) as u8);
+        }
+        assert_eq!(inflate(Format::Gzip, &deflate(Format::Gzip, 2048, &[&random])), random);
+        assert_eq!(inflate(Format::Deflate, &deflate(Format::Deflate, 1024, &[])), b"");
+    }
+
+    #[test]
+    fn test_deflater_reuse() {
+        let mut window = [0u8; 1024];
+        let mut deflater = Deflater::new(Format::Gzip, &mut window);
+        let mut first = MemoryChannel::new(b"");
+        deflater.write(&mut first, b"first first first").unwrap();
+        deflater.finish(&mut first).unwrap();
+        let mut second = MemoryChannel::new(b"");
+        deflater.write(&mut second, b"second").unwrap();
+        deflater.finish(&mut second).unwrap();
+        assert_eq!(inflate(Format::Gzip, first.sent()), b"first first first");
+        assert_eq!(inflate(Format::Gzip, second.sent()), b"second");
+    }
+}
@@ -0,0 +1,455 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Resumable downloads with range requests (RFC 7233). Interrupted downloads continue from the
+// last byte received, and start over when the resource changed meanwhile.
+
+use collections::String;
+use core::cmp;
+use core::str::FromStr;
+
+use chunked::ChunkedReader;
+use traits::{Channel, ChannelError, Sleep};
+use {Client, HttpError, HttpHeader};
+
+// Where the downloaded data goes, eg. a file or a flash partition.
+pub trait Sink {
+    // Appends data after what was already written.
+    fn write(&mut self, data: &[u8]) -> Result<(), HttpError>;
+
+    // Discards all the data written, before downloading a changed resource again.
+    fn reset(&mut self) -> Result<(), HttpError>;
+}
+
+// Parses a Content-Range value into its first and last byte positions, and the complete
+// length if known. Unsatisfied ranges (`bytes */length`) have no positions.
+pub fn parse_content_range(value: &str) -> Option<(Option<(u64, u64)>, Option<u64>)> {
+    let value = value.trim();
+    if value.len() < 6 || !value[..6].eq_ignore_ascii_case("bytes ") {
+        return None;
+    }
+    let mut parts = value[6..].splitn(2, '/');
+    let range = parts.next()?.trim();
+    let length = match parts.next()?.trim() {
+        "*" => None,
+        length => Some(parse_number(length)?),
+    };
+    if range == "*" {
+        return length.map(|length| (None, Some(length)));
+    }
+    let mut positions = range.splitn(2, '-');
+    let first = parse_number(positions.next()?)?;
+    let last = parse_number(positions.next()?)?;
+    if last < first || length.map_or(false, |length| last >= length) {
+        return None;
+    }
+    Some((Some((first, last)), length))
+}
+
+fn parse_number(value: &str) -> Option<u64> {
+    if value.is_empty() || !value.bytes().all(|value| value.is_ascii_digit()) {
+        return None;
+    }
+    u64::from_str(value).ok()
+}
+
+pub struct Download<'u> {
+    url: &'u str,
+    // Bytes already written to the sink.
+    offset: u64,
+    // The entity tag, or else the Last-Modified date, identifying the downloaded content.
+    validator: Option<String>,
+    // The complete length of the resource, when known.
+    length: Option<u64>,
+    complete: bool,
+    // Delay between attempts, in milliseconds.
+    retry: u32,
+    max_failures: Option<u32>,
+}
+
+impl<'u> Download<'u> {
+    pub fn new(url: &'u str) -> Self {
+        Download {
+            url: url,
+            offset: 0,
+            validator: None,
+            length: None,
+            complete: false,
+            retry: 3000,
+            max_failures: None,
+        }
+    }
+
+    // Continues a download saved with offset() and validator(), eg. before a reboot. Without a
+    // validator, the server can't tell us if the resource changed.
+    pub fn resume(url: &'u str, offset: u64, validator: Option<String>) -> Self {
+        Download {
+            offset: offset,
+            validator: validator,
+            ..Download::new(url)
+        }
+    }
+
+    // Starts over from the beginning of the resource, keeping the retry settings.
+    pub fn restart(&mut self) {
+        self.offset = 0;
+        self.validator = None;
+        self.length = None;
+        self.complete = false;
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn validator(&self) -> Option<&str> {
+        self.validator.as_ref().map(|validator| validator.as_str())
+    }
+
+    pub fn length(&self) -> Option<u64> {
+        self.length
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    // The delay before trying again after a failure.
+    pub fn set_retry(&mut self, ms: u32) {
+        self.retry = ms;
+    }
+
+    // Gives up after this many failed attempts in a row. The default is to retry forever.
+    pub fn set_max_failures(&mut self, max: Option<u32>) {
+        self.max_failures = max;
+    }
+
+    // Fetches the resource until it is complete, resuming after connection failures. Attempts
+    // receiving some data reset the failure count. Other errors, such as unexpected responses
+    // or sink failures, end the download.
+    pub fn run<T, S, P>(&mut self,
+                        client: &mut Client<'u, T>,
+                        sink: &mut S,
+                        sleep: &mut P)
+                        -> Result<(), HttpError>
+        where T: Channel,
+              S: Sink,
+              P: Sleep
+    {
+        let mut failures = 0;
+        while !self.complete {
+            let offset = self.offset;
+            let err = match self.fetch(client, sink) {
+                Ok(_) => HttpError::ChannelError(ChannelError::EndOfStream),
+                Err(err @ HttpError::ChannelError(_)) => err,
+                Err(err) => return Err(err),
+            };
+            if self.complete {
+                break;
+            }
+            if self.offset == offset {
+                failures += 1;
+                if self.max_failures.map(|max| failures > max) == Some(true) {
+                    return Err(err);
+                }
+            } else {
+                failures = 0;
+            }
+            sleep.sleep_ms(self.retry);
+        }
+        Ok(())
+    }
+
+    // Makes a single request, writing the body to the sink. Returns true once the download is
+    // complete. The data received before an error is kept, for the next attempt to resume.
+    pub fn fetch<T, S>(&mut self,
+                       client: &mut Client<'u, T>,
+                       sink: &mut S)
+                       -> Result<bool, HttpError>
+        where T: Channel,
+              S: Sink
+    {
+        let request = client.get(self.url).open()?;
+        if self.offset > 0 {
+            request.header(HttpHeader::Range, &format!("bytes={}-", self.offset))?;
+            if let Some(ref validator) = self.validator {
+                request.header(HttpHeader::IfRange, validator)?;
+            }
+        }
+        let response = request.response(|header| {
+                header == HttpHeader::ContentLength || header == HttpHeader::ContentRange ||
+                header == HttpHeader::Etag ||
+                header == HttpHeader::LastModified ||
+                header == HttpHeader::TransferEncoding
+            })?;
+        let header = |name: HttpHeader| {
+            response.headers
+                .iter()
+                .find(|header| header.0 == name)
+                .map(|header| header.1.trim())
+        };
+
+        let remaining = match response.status_code {
+            206 => {
+                let (first, last, length) = match header(HttpHeader::ContentRange)
+                    .and_then(parse_content_range) {
+                    Some((Some((first, last)), length)) => (first, last, length),
+                    _ => return Err(HttpError::InvalidContentRange),
+                };
+                if first != self.offset {
+                    return Err(HttpError::InvalidContentRange);
+                }
+                self.length = length;
+                Some(last + 1 - first)
+            }
+            // The whole resource, because the server ignored the range or the resource changed.
+            200 => {
+                if self.offset > 0 {
+                    sink.reset()?;
+                    self.offset = 0;
+                }
+                self.length = header(HttpHeader::ContentLength).and_then(parse_number);
+                self.length
+            }
+            // We already have everything.
+            416 if self.offset > 0 => {
+                match header(HttpHeader::ContentRange).and_then(parse_content_range) {
+                    Some((None, Some(length))) if length == self.offset => {
+                        self.length = Some(length);
+                        self.complete = true;
+                        return Ok(true);
+                    }
+                    _ => return Err(HttpError::UnexpectedStatus(416)),
+                }
+            }
+            status => return Err(HttpError::UnexpectedStatus(status)),
+        };
+
+        // Only strong entity tags can be used with If-Range.
+        let validator = match header(HttpHeader::Etag) {
+            Some(etag) if !etag.starts_with("W/") => Some(etag),
+            _ => header(HttpHeader::LastModified),
+        };
+        if let Some(validator) = validator {
+            self.validator = Some(String::from(validator));
+        }
+
+        let chunked = header(HttpHeader::TransferEncoding)
+            .map_or(false, |value| value.eq_ignore_ascii_case("chunked"));
+        let ended = if chunked {
+            self.copy_body(&mut ChunkedReader::new(response.body), sink, remaining)?
+        } else {
+            self.copy_body(response.body, sink, remaining)?
+        };
+
+        // Without a length, the body ends with the connection.
+        self.complete = match (remaining, self.length) {
+            (_, Some(length)) => self.offset == length,
+            (None, None) => ended,
+            (Some(_), None) => false,
+        };
+        Ok(self.complete)
+    }
+
+    // Copies at most `remaining` bytes of the body to the sink. Returns true if the body
+    // ended before.
+    fn copy_body<C, S>(&mut self,
+                       body: &mut C,
+                       sink: &mut S,
+                       mut remaining: Option<u64>)
+                       -> Result<bool, HttpError>
+        where C: Channel,
+              S: Sink
+    {
+        let mut buffer = [0u8; 512];
+        loop {
+            let size = match remaining {
+                Some(0) => return Ok(false),
+                Some(remaining) => cmp::min(remaining, buffer.len() as u64) as usize,
+                None => buffer.len(),
+            };
+            let read = body.read_to_end(&mut buffer, size)?;
+            sink.write(&buffer[..read])?;
+            self.offset += read as u64;
+            remaining = remaining.map(|remaining| remaining - read as u64);
+            if read < size {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use collections::Vec;
+    use core::str;
+    use traits::StringChannel;
+
+    // Answers each connection with the next response.
+    struct ScriptChannel {
+        responses: Vec<&'static str>,
+        current: StringChannel<'static>,
+        requests: Vec<String>,
+    }
+
+    impl ScriptChannel {
+        fn new(responses: &[&'static str]) -> Self {
+            ScriptChannel {
+                responses: responses.to_vec(),
+                current: StringChannel::new(""),
+                requests: Vec::new(),
+            }
+        }
+    }
+
+    impl Channel for ScriptChannel {
+        fn open(&mut self, _: &str, _: u16, _: bool) -> Result<(), ChannelError> {
+            if self.responses.is_empty() {
+                return Err(ChannelError::UnableToConnect);
+            }
+            self.current = StringChannel::new(self.responses.remove(0));
+            self.requests.push(String::new());
+            Ok(())
+        }
+
+        fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+            self.requests.last_mut().unwrap().push_str(str::from_utf8(&data[..len]).unwrap());
+            Ok(len)
+        }
+
+        fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+            self.current.recv(data, max_len)
+        }
+    }
+
+    struct Buffer {
+        data: Vec<u8>,
+        resets: usize,
+    }
+
+    impl Sink for Buffer {
+        fn write(&mut self, data: &[u8]) -> Result<(), HttpError> {
+            self.data.extend_from_slice(data);
+            Ok(())
+        }
+
+        fn reset(&mut self) -> Result<(), HttpError> {
+            self.data.clear();
+            self.resets += 1;
+            Ok(())
+        }
+    }
+
+    struct Sleeps(Vec<u32>);
+
+    impl Sleep for Sleeps {
+        fn sleep_ms(&mut self, ms: u32) {
+            self.0.push(ms);
+        }
+    }
+
+    #[test]
+    fn test_content_range() {
+        assert_eq!(parse_content_range("bytes 0-9/10"), Some((Some((0, 9)), Some(10))));
+        assert_eq!(parse_content_range("Bytes 5-9/*"), Some((Some((5, 9)), None)));
+        assert_eq!(parse_content_range("bytes */10"), Some((None, Some(10))));
+        assert_eq!(parse_content_range("bytes 5-10/10"), None);
+        assert_eq!(parse_content_range("bytes 9-5/10"), None);
+        assert_eq!(parse_content_range("bytes */*"), None);
+        assert_eq!(parse_content_range("items 0-9/10"), None);
+        assert_eq!(parse_content_range("bytes -1-9/10"), None);
+    }
+
+    #[test]
+    fn test_resume() {
+        // The connection drops after 4 bytes, and the server sends the rest of the resource.
+        let mut channel = ScriptChannel::new(&["HTTP/1.1 200 OK\r\nETag: \
+                                                \"v1\"\r\nContent-Length: 10\r\n\r\n0123",
+                                               "HTTP/1.1 206 Partial Content\r\nETag: \
+                                                \"v1\"\r\nContent-Range: bytes \
+                                                4-9/10\r\nContent-Length: 6\r\n\r\n456789"]);
+        let mut sink = Buffer {
+            data: Vec::new(),
+            resets: 0,
+        };
+        let mut sleeps = Sleeps(Vec::new());
+        {
+            let mut client = Client::new(&mut channel);
+            let mut download = Download::new("http://localhost/firmware.bin");
+            download.set_retry(100);
+            download.run(&mut client, &mut sink, &mut sleeps).unwrap();
+            assert!(download.is_complete());
+            assert_eq!(download.offset(), 10);
+            assert_eq!(download.length(), Some(10));
+            assert_eq!(download.validator(), Some("\"v1\""));
+        }
+        assert_eq!(sink.data, b"0123456789");
+        assert_eq!(sleeps.0, [100]);
+        assert_eq!(channel.requests[0],
+                   "GET /firmware.bin HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(channel.requests[1],
+                   "GET /firmware.bin HTTP/1.1\r\nHost: localhost\r\nRange: \
+                    bytes=4-\r\nIf-Range: \"v1\"\r\n\r\n");
+    }
+
+    #[test]
+    fn test_changed_resource() {
+        // The resource changed since the first part was saved, so it comes whole.
+        let mut channel = ScriptChannel::new(&["HTTP/1.1 200 OK\r\nLast-Modified: Tue, 15 Nov \
+                                                1994 12:45:26 GMT\r\nTransfer-Encoding: \
+                                                chunked\r\n\r\n3\r\nnew\r\n0\r\n\r\n"]);
+        let mut sink = Buffer {
+            data: b"old".to_vec(),
+            resets: 0,
+        };
+        {
+            let mut client = Client::new(&mut channel);
+            let mut download = Download::resume("http://localhost/config",
+                                                3,
+                                                Some(String::from("\"v1\"")));
+            assert!(download.fetch(&mut client, &mut sink).unwrap());
+            assert_eq!(download.offset(), 3);
+            assert_eq!(download.validator(), Some("Tue, 15 Nov 1994 12:45:26 GMT"));
+        }
+        assert_eq!(sink.data, b"new");
+        assert_eq!(sink.resets, 1);
+        assert!(channel.requests[0].ends_with("Range: bytes=3-\r\nIf-Range: \"v1\"\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_download_errors() {
+        let mut sink = Buffer {
+            data: b"0123".to_vec(),
+            resets: 0,
+        };
+        let mut sleeps = Sleeps(Vec::new());
+
+        // The range doesn't start where we are.
+        let mut channel = ScriptChannel::new(&["HTTP/1.1 206 Partial Content\r\nContent-Range: \
+                                                bytes 0-9/10\r\n\r\n0123456789"]);
+        let mut download = Download::resume("http://localhost/a", 4, None);
+        assert_eq!(download.run(&mut Client::new(&mut channel), &mut sink, &mut sleeps),
+                   Err(HttpError::InvalidContentRange));
+
+        // Everything was already received.
+        let mut channel = ScriptChannel::new(&["HTTP/1.1 416 Range Not \
+                                                Satisfiable\r\nContent-Range: bytes */4\r\n\r\n"]);
+        download.run(&mut Client::new(&mut channel), &mut sink, &mut sleeps).unwrap();
+        assert_eq!(download.length(), Some(4));
+
+        let mut channel = ScriptChannel::new(&["HTTP/1.1 404 Not Found\r\n\r\n"]);
+        let mut download = Download::new("http://localhost/a");
+        assert_eq!(download.run(&mut Client::new(&mut channel), &mut sink, &mut sleeps),
+                   Err(HttpError::UnexpectedStatus(404)));
+
+        // Nobody answers anymore.
+        let mut channel = ScriptChannel::new(&[]);
+        download.set_max_failures(Some(2));
+        assert_eq!(download.run(&mut Client::new(&mut channel), &mut sink, &mut sleeps),
+                   Err(HttpError::ChannelError(ChannelError::UnableToConnect)));
+        assert_eq!(sleeps.0, [3000, 3000]);
+        assert_eq!(sink.data, b"0123");
+    }
+}
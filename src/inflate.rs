@@ -0,0 +1,588 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// A streaming decoder for deflate data (RFC 1951), in the gzip (RFC 1952) and zlib (RFC 1950)
+// formats used by the gzip and deflate content codings.
+//
+// The history needed by back references lives in a window buffer provided by the caller. Data
+// is read from the channel one byte at a time, so nothing past the compressed data is consumed.
+
+use checksum::{Adler32, Crc32};
+use traits::{Channel, ChannelError};
+use HttpError;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InflateError {
+    InvalidHeader,
+    InvalidBlockType,
+    InvalidStoredLength,
+    InvalidCodeLengths,
+    InvalidCode,
+    // A back reference goes further than the window buffer, or before the start of the data.
+    DistanceTooFar,
+    ChecksumMismatch,
+    UnexpectedEnd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Gzip,
+    // The zlib format, or raw deflate data as sent by some servers.
+    Deflate,
+}
+
+impl Format {
+    // The format of a Content-Encoding header value, None for the identity coding.
+    pub fn from_content_encoding(value: &str) -> Result<Option<Format>, HttpError> {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("gzip") || value.eq_ignore_ascii_case("x-gzip") {
+            Ok(Some(Format::Gzip))
+        } else if value.eq_ignore_ascii_case("deflate") {
+            Ok(Some(Format::Deflate))
+        } else if value.is_empty() || value.eq_ignore_ascii_case("identity") {
+            Ok(None)
+        } else {
+            Err(HttpError::UnsupportedEncoding)
+        }
+    }
+}
+
+// The base values and extra bits of length and distance codes, shared with the encoder.
+pub static LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35,
+                                     43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+pub static LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4,
+                                     4, 4, 4, 5, 5, 5, 5, 0];
+pub static DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                                       257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+                                       8193, 12289, 16385, 24577];
+pub static DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8,
+                                       9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// The order of the code length code lengths in a dynamic block header.
+static CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2,
+                                         14, 1, 15];
+
+// A canonical Huffman code, decoded one bit at a time: slow, but small.
+struct Huffman {
+    // The number of codes of each length.
+    counts: [u16; 16],
+    // The symbols, ordered by code.
+    symbols: [u16; 288],
+}
+
+impl Huffman {
+    fn new() -> Self {
+        Huffman {
+            counts: [0; 16],
+            symbols: [0; 288],
+        }
+    }
+
+    fn build(&mut self, lengths: &[u8]) -> Result<(), InflateError> {
+        self.counts = [0; 16];
+        for length in lengths {
+            self.counts[*length as usize] += 1;
+        }
+
+        // Reject over-subscribed codes. Incomplete ones are only an error if an unused code
+        // is met.
+        let mut left: i32 = 1;
+        for length in 1..16 {
+            left = (left << 1) - self.counts[length] as i32;
+            if left < 0 {
+                return Err(InflateError::InvalidCodeLengths);
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + self.counts[length];
+        }
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                self.symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Header,
+    BlockHeader,
+    Stored(u16),
+    Codes,
+    Trailer,
+    Done,
+}
+
+/// Decompresses a body read from a channel.
+pub struct InflateReader<'a, T> {
+    channel: T,
+    format: Format,
+    // The zlib header was missing, for raw deflate data.
+    raw: bool,
+    state: State,
+    last_block: bool,
+    bit_buffer: u32,
+    bit_count: u32,
+    literals: Huffman,
+    distances: Huffman,
+    window: &'a mut [u8],
+    window_pos: usize,
+    // The number of bytes of history in the window.
+    window_len: usize,
+    // A back reference being copied.
+    copy_length: usize,
+    copy_distance: usize,
+    crc: Crc32,
+    adler: Adler32,
+}
+
+impl<'a, T: Channel> InflateReader<'a, T> {
+    // The window must be at least as large as the one used by the compressor, 32 KiB in
+    // general. Back references further away are reported as errors.
+    pub fn new(channel: T, format: Format, window: &'a mut [u8]) -> Self {
+        InflateReader {
+            channel: channel,
+            format: format,
+            raw: false,
+            state: State::Header,
+            last_block: false,
+            bit_buffer: 0,
+            bit_count: 0,
+            literals: Huffman::new(),
+            distances: Huffman::new(),
+            window: window,
+            window_pos: 0,
+            window_len: 0,
+            copy_length: 0,
+            copy_distance: 0,
+            crc: Crc32::new(),
+            adler: Adler32::new(),
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, HttpError> {
+        while self.bit_count < count {
+            let mut next = [0u8];
+            match self.channel.recv(&mut next, 1) {
+                Ok(1) => {}
+                Ok(_) | Err(ChannelError::EndOfStream) => {
+                    return Err(HttpError::Inflate(InflateError::UnexpectedEnd))
+                }
+                Err(err) => return Err(HttpError::from(err)),
+            }
+            self.bit_buffer |= (next[0] as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1u64 << count) - 1) as u32;
+        self.bit_buffer = if count == 32 { 0 } else { self.bit_buffer >> count };
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    // Skips the bits up to the next byte boundary.
+    fn align(&mut self) {
+        let count = self.bit_count % 8;
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+    }
+
+    fn byte(&mut self) -> Result<u8, HttpError> {
+        Ok(self.bits(8)? as u8)
+    }
+
+    // Reads a little endian value of `count` bytes.
+    fn bytes_le(&mut self, count: u32) -> Result<u32, HttpError> {
+        let mut value = 0;
+        for i in 0..count {
+            value |= (self.byte()? as u32) << (i * 8);
+        }
+        Ok(value)
+    }
+
+    fn decode(&mut self, distances: bool) -> Result<u16, HttpError> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..16 {
+            code |= self.bits(1)? as i32;
+            let count = if distances {
+                self.distances.counts[length]
+            } else {
+                self.literals.counts[length]
+            } as i32;
+            if code - first < count {
+                let symbol = (index + code - first) as usize;
+                return Ok(if distances {
+                    self.distances.symbols[symbol]
+                } else {
+                    self.literals.symbols[symbol]
+                });
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(HttpError::Inflate(InflateError::InvalidCode))
+    }
+
+    fn read_header(&mut self) -> Result<(), HttpError> {
+        let invalid = HttpError::Inflate(InflateError::InvalidHeader);
+        match self.format {
+            Format::Gzip => {
+                if self.byte()? != 0x1f || self.byte()? != 0x8b || self.byte()? != 8 {
+                    return Err(invalid);
+                }
+                let flags = self.byte()?;
+                // Skip the modification time, extra flags and OS.
+                self.bytes_le(4)?;
+                self.bytes_le(2)?;
+                if flags & 0x04 != 0 {
+                    let length = self.bytes_le(2)?;
+                    for _ in 0..length {
+                        self.byte()?;
+                    }
+                }
+                // The file name and comment are zero terminated.
+                for flag in [0x08, 0x10].iter() {
+                    if flags & flag != 0 {
+                        while self.byte()? != 0 {}
+                    }
+                }
+                if flags & 0x02 != 0 {
+                    self.bytes_le(2)?;
+                }
+            }
+            Format::Deflate => {
+                let cmf = self.byte()?;
+                let flags = self.byte()?;
+                let zlib = cmf & 0x0f == 8 && cmf >> 4 <= 7 &&
+                           ((cmf as u16) << 8 | flags as u16) % 31 == 0;
+                if !zlib {
+                    // Raw deflate data: decode these bytes as the first block.
+                    self.raw = true;
+                    self.bit_buffer = cmf as u32 | (flags as u32) << 8;
+                    self.bit_count = 16;
+                } else if flags & 0x20 != 0 {
+                    // Preset dictionaries are not used by HTTP.
+                    return Err(invalid);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_block_header(&mut self) -> Result<(), HttpError> {
+        if self.last_block {
+            self.state = State::Trailer;
+            return Ok(());
+        }
+        self.last_block = self.bits(1)? == 1;
+        match self.bits(2)? {
+            0 => {
+                self.align();
+                let length = self.bits(16)?;
+                if self.bits(16)? != !length & 0xffff {
+                    return Err(HttpError::Inflate(InflateError::InvalidStoredLength));
+                }
+                self.state = State::Stored(length as u16);
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                for (symbol, length) in lengths.iter_mut().enumerate() {
+                    *length = match symbol {
+                        0..=143 => 8,
+                        144..=255 => 9,
+                        256..=279 => 7,
+                        _ => 8,
+                    };
+                }
+                self.literals.build(&lengths)?;
+                self.distances.build(&[5; 30])?;
+                self.state = State::Codes;
+            }
+            2 => {
+                self.read_dynamic_codes()?;
+                self.state = State::Codes;
+            }
+            _ => return Err(HttpError::Inflate(InflateError::InvalidBlockType)),
+        }
+        Ok(())
+    }
+
+    fn read_dynamic_codes(&mut self) -> Result<(), HttpError> {
+        let invalid = HttpError::Inflate(InflateError::InvalidCodeLengths);
+        let literal_count = self.bits(5)? as usize + 257;
+        let distance_count = self.bits(5)? as usize + 1;
+        let code_length_count = self.bits(4)? as usize + 4;
+        if literal_count > 286 || distance_count > 30 {
+            return Err(invalid);
+        }
+
+        // The code lengths are themselves compressed, with a code decoded through the
+        // distance table.
+        let mut lengths = [0u8; 316];
+        for i in 0..code_length_count {
+            lengths[CODE_LENGTH_ORDER[i]] = self.bits(3)? as u8;
+        }
+        self.distances.build(&lengths[..19])?;
+
+        let mut i = 0;
+        while i < literal_count + distance_count {
+            let symbol = self.decode(true)?;
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => {
+                    if i == 0 {
+                        return Err(invalid);
+                    }
+                    (lengths[i - 1], 3 + self.bits(2)? as usize)
+                }
+                17 => (0, 3 + self.bits(3)? as usize),
+                _ => (0, 11 + self.bits(7)? as usize),
+            };
+            if i + repeat > literal_count + distance_count {
+                return Err(invalid);
+            }
+            for length in lengths[i..i + repeat].iter_mut() {
+                *length = value;
+            }
+            i += repeat;
+        }
+
+        // The end of block code is required.
+        if lengths[256] == 0 {
+            return Err(invalid);
+        }
+        self.literals.build(&lengths[..literal_count])?;
+        self.distances.build(&lengths[literal_count..literal_count + distance_count])?;
+        Ok(())
+    }
+
+    fn read_trailer(&mut self) -> Result<(), HttpError> {
+        self.align();
+        let valid = match self.format {
+            Format::Gzip => {
+                self.bytes_le(4)? == self.crc.digest() && self.bytes_le(4)? == self.crc.length()
+            }
+            Format::Deflate if self.raw => true,
+            Format::Deflate => self.bytes_le(4)?.swap_bytes() == self.adler.digest(),
+        };
+        if !valid {
+            return Err(HttpError::Inflate(InflateError::ChecksumMismatch));
+        }
+        self.state = State::Done;
+        Ok(())
+    }
+
+    fn output(&mut self, value: u8) {
+        if !self.window.is_empty() {
+            self.window[self.window_pos] = value;
+            self.window_pos = (self.window_pos + 1) % self.window.len();
+            if self.window_len < self.window.len() {
+                self.window_len += 1;
+            }
+        }
+        match self.format {
+            Format::Gzip => self.crc.update(&[value]),
+            Format::Deflate => self.adler.update(&[value]),
+        }
+    }
+
+    // Decompresses data into the buffer, returning the number of bytes written. Returns 0 at
+    // the end of the data, once the checksum was verified.
+    pub fn read(&mut self, data: &mut [u8]) -> Result<usize, HttpError> {
+        let mut size = 0;
+        while size < data.len() {
+            if self.copy_length > 0 {
+                let pos = (self.window_pos + self.window.len() - self.copy_distance) %
+                          self.window.len();
+                let value = self.window[pos];
+                self.output(value);
+                data[size] = value;
+                size += 1;
+                self.copy_length -= 1;
+                continue;
+            }
+
+            match self.state {
+                State::Header => {
+                    self.read_header()?;
+                    self.state = State::BlockHeader;
+                }
+                State::BlockHeader => self.read_block_header()?,
+                State::Stored(0) => self.state = State::BlockHeader,
+                State::Stored(length) => {
+                    let value = self.byte()?;
+                    self.output(value);
+                    data[size] = value;
+                    size += 1;
+                    self.state = State::Stored(length - 1);
+                }
+                State::Codes => {
+                    let symbol = self.decode(false)? as usize;
+                    if symbol < 256 {
+                        self.output(symbol as u8);
+                        data[size] = symbol as u8;
+                        size += 1;
+                    } else if symbol == 256 {
+                        self.state = State::BlockHeader;
+                    } else {
+                        let symbol = symbol - 257;
+                        if symbol >= LENGTH_BASE.len() {
+                            return Err(HttpError::Inflate(InflateError::InvalidCode));
+                        }
+                        let length = LENGTH_BASE[symbol] as usize +
+                                     self.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+                        let symbol = self.decode(true)? as usize;
+                        if symbol >= DISTANCE_BASE.len() {
+                            return Err(HttpError::Inflate(InflateError::InvalidCode));
+                        }
+                        let distance = DISTANCE_BASE[symbol] as usize +
+                                       self.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+                        if distance > self.window_len {
+                            return Err(HttpError::Inflate(InflateError::DistanceTooFar));
+                        }
+                        self.copy_length = length;
+                        self.copy_distance = distance;
+                    }
+                }
+                State::Trailer => self.read_trailer()?,
+                State::Done => break,
+            }
+        }
+        Ok(size)
+    }
+}
+
+// Lets the decompressed body be read like the raw one. Corrupted data is reported as an
+// InvalidString error: use read() to get the details.
+impl<'a, T: Channel> Channel for InflateReader<'a, T> {
+    fn open(&mut self, host: &str, port: u16, tls: bool) -> Result<(), ChannelError> {
+        self.channel.open(host, port, tls)
+    }
+
+    fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+        self.channel.send(data, len)
+    }
+
+    fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+        match self.read(&mut data[..max_len]) {
+            Ok(0) if max_len != 0 => Err(ChannelError::EndOfStream),
+            Ok(size) => Ok(size),
+            Err(HttpError::ChannelError(err)) => Err(err),
+            Err(_) => Err(ChannelError::InvalidString),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use collections::{String, Vec};
+    use traits::MemoryChannel;
+
+    // "Hello, Hello, Hello, World!\n", with fixed codes.
+    static GZIP: [u8; 36] = [0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xf3,
+                             0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0xf0, 0x40, 0xa1, 0xc2, 0xf3,
+                             0x8b, 0x72, 0x52, 0x14, 0xb9, 0x00, 0xbc, 0x0c, 0xa6, 0xe5, 0x1c,
+                             0x00, 0x00, 0x00];
+
+    // 100 lines of text, with dynamic codes.
+    static ZLIB: [u8; 263] = [0x78, 0xda, 0x7d, 0xd6, 0x4b, 0x4e, 0x43, 0x31, 0x0c, 0x40, 0xd1,
+                              0x39, 0xab, 0x78, 0x4b, 0x20, 0x71, 0x3e, 0x36, 0xcb, 0x01, 0x15,
+                              0x51, 0x51, 0xb5, 0x02, 0x81, 0x60, 0xf9, 0x88, 0x05, 0xf4, 0x8c,
+                              0xef, 0x28, 0x47, 0x89, 0xe3, 0xcb, 0xf9, 0x7a, 0x3a, 0x1e, 0x9f,
+                              0x8e, 0xaf, 0xb7, 0xd3, 0xf1, 0xf1, 0x7d, 0x7e, 0x79, 0x3f, 0x9e,
+                              0x3f, 0x6f, 0x3f, 0xd7, 0xe3, 0xf5, 0xf6, 0xfb, 0x70, 0xf9, 0x6f,
+                              0x0d, 0xad, 0xa3, 0x05, 0xda, 0x40, 0x9b, 0x68, 0x0b, 0x6d, 0xa3,
+                              0x25, 0x5a, 0xe9, 0xec, 0x84, 0x91, 0x4c, 0x13, 0x4d, 0x93, 0x4d,
+                              0x13, 0x4e, 0x93, 0x4e, 0x13, 0x4f, 0x93, 0x4f, 0x13, 0x50, 0x93,
+                              0x50, 0x97, 0x50, 0xe7, 0xdd, 0x91, 0x50, 0x97, 0x50, 0x97, 0x50,
+                              0x97, 0x50, 0x97, 0x50, 0x97, 0x50, 0x97, 0x50, 0x97, 0x50, 0x48,
+                              0x28, 0x24, 0x14, 0x7c, 0x5e, 0x12, 0x0a, 0x09, 0x85, 0x84, 0x42,
+                              0x42, 0x21, 0xa1, 0x90, 0x50, 0x48, 0x68, 0x48, 0x68, 0x48, 0x68,
+                              0x48, 0x68, 0x70, 0x02, 0x49, 0x68, 0x48, 0x68, 0x48, 0x68, 0x48,
+                              0x68, 0x48, 0x68, 0x48, 0x68, 0x4a, 0x68, 0x4a, 0x68, 0x4a, 0x68,
+                              0x4a, 0x68, 0x72, 0x48, 0x4b, 0x68, 0x4a, 0x68, 0x4a, 0x68, 0x4a,
+                              0x68, 0x4a, 0x68, 0x49, 0x68, 0x49, 0x68, 0x49, 0x68, 0x49, 0x68,
+                              0x49, 0x68, 0xf1, 0x1f, 0x93, 0xd0, 0x92, 0xd0, 0x92, 0xd0, 0x92,
+                              0xd0, 0x96, 0xd0, 0x96, 0xd0, 0x96, 0xd0, 0x96, 0xd0, 0x96, 0xd0,
+                              0x96, 0xd0, 0xe6, 0x57, 0x2f, 0xa1, 0x2d, 0xa1, 0x2d, 0xa1, 0x94,
+                              0x50, 0x4a, 0x28, 0x25, 0x94, 0x12, 0x4a, 0x09, 0xa5, 0x84, 0x52,
+                              0x42, 0xc9, 0x6d, 0x48, 0x42, 0x29, 0xa1, 0x92, 0x50, 0x49, 0xa8,
+                              0x24, 0x54, 0x12, 0x2a, 0x09, 0x95, 0x84, 0x4a, 0x42, 0x25, 0xa1,
+                              0xe2, 0xc2, 0x78, 0x47, 0xe8, 0x0f, 0x90, 0x0a, 0xd0, 0x6e];
+
+    fn inflate(data: &[u8], format: Format, window_size: usize) -> Result<Vec<u8>, HttpError> {
+        let mut channel = MemoryChannel::new(data);
+        let mut window = vec![0u8; window_size];
+        let mut reader = InflateReader::new(&mut channel, format, &mut window);
+        let mut res = Vec::new();
+        let mut buffer = [0u8; 7];
+        loop {
+            let size = reader.read(&mut buffer)?;
+            if size == 0 {
+                return Ok(res);
+            }
+            res.extend_from_slice(&buffer[..size]);
+        }
+    }
+
+    #[test]
+    fn test_inflate() {
+        assert_eq!(inflate(&GZIP, Format::Gzip, 32768).unwrap(),
+                   b"Hello, Hello, Hello, World!\n");
+
+        let mut text = String::new();
+        for i in 0..100 {
+            text.push_str(&format!("line {}: the quick brown fox\n", i));
+        }
+        assert_eq!(inflate(&ZLIB, Format::Deflate, 4096).unwrap(), text.as_bytes());
+
+        // Raw deflate data with a stored block, as sent by some servers.
+        assert_eq!(inflate(&[0x01, 0x0b, 0x00, 0xf4, 0xff, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x64,
+                             0x20, 0x64, 0x61, 0x74, 0x61],
+                           Format::Deflate,
+                           0)
+                       .unwrap(),
+                   b"stored data");
+    }
+
+    #[test]
+    fn test_inflate_channel() {
+        let mut channel = MemoryChannel::new(&GZIP);
+        let mut window = [0u8; 64];
+        let mut reader = InflateReader::new(&mut channel, Format::Gzip, &mut window);
+        let mut buffer = [0u8; 64];
+        assert_eq!(reader.read_string_to_end(&mut buffer).unwrap(),
+                   "Hello, Hello, Hello, World!\n");
+    }
+
+    #[test]
+    fn test_inflate_errors() {
+        let mut corrupted = GZIP;
+        corrupted[28] ^= 1;
+        assert_eq!(inflate(&corrupted, Format::Gzip, 32768).err().unwrap(),
+                   HttpError::Inflate(InflateError::ChecksumMismatch));
+        assert_eq!(inflate(&GZIP[..20], Format::Gzip, 32768).err().unwrap(),
+                   HttpError::Inflate(InflateError::UnexpectedEnd));
+        assert_eq!(inflate(&ZLIB[2..], Format::Gzip, 32768).err().unwrap(),
+                   HttpError::Inflate(InflateError::InvalidHeader));
+        assert_eq!(inflate(&ZLIB, Format::Deflate, 16).err().unwrap(),
+                   HttpError::Inflate(InflateError::DistanceTooFar));
+        assert_eq!(inflate(&[0x07, 0x00], Format::Deflate, 16).err().unwrap(),
+                   HttpError::Inflate(InflateError::InvalidBlockType));
+
+        let mut channel = MemoryChannel::new(&corrupted);
+        let mut window = [0u8; 64];
+        let mut reader = InflateReader::new(&mut channel, Format::Gzip, &mut window);
+        let mut buffer = [0u8; 64];
+        assert_eq!(reader.read_string_to_end(&mut buffer).err().unwrap(),
+                   ChannelError::InvalidString);
+    }
+}
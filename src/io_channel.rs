@@ -0,0 +1,222 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// A `Channel` implementation over an already connected embedded-io byte stream,
+// like a serial link or a socket provided by the platform.
+
+use embedded_io::{Error, ErrorKind, Read, Write};
+
+use traits::{Channel, ChannelError};
+
+pub struct IoChannel<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R, W> IoChannel<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        IoChannel {
+            reader: reader,
+            writer: writer,
+        }
+    }
+
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+}
+
+fn channel_error<E: Error>(err: E) -> ChannelError {
+    match err.kind() {
+        ErrorKind::TimedOut => ChannelError::Timeout,
+        ErrorKind::NotConnected => ChannelError::NotConnected,
+        ErrorKind::ConnectionRefused => ChannelError::UnableToConnect,
+        ErrorKind::ConnectionReset |
+        ErrorKind::ConnectionAborted |
+        ErrorKind::BrokenPipe => ChannelError::EndOfStream,
+        _ => ChannelError::SomethingWentWrong,
+    }
+}
+
+impl<R: Read, W: Write> Channel for IoChannel<R, W> {
+    // The stream is connected by its owner, so there is nothing to open.
+    fn open(&mut self, _: &str, _: u16, tls: bool) -> Result<(), ChannelError> {
+        if tls {
+            return Err(ChannelError::TlsUnsupported);
+        }
+        Ok(())
+    }
+
+    // Writes can be partial, so we loop until everything is written.
+    fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+        let mut sent = 0;
+        while sent < len {
+            match self.writer.write(&data[sent..len]) {
+                Ok(0) => return Err(ChannelError::EndOfStream),
+                Ok(size) => sent += size,
+                Err(err) => return Err(channel_error(err)),
+            }
+        }
+        // Make sure buffered writers don't hold the request back while we wait for the response.
+        self.writer.flush().map_err(channel_error)?;
+        Ok(len)
+    }
+
+    fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+        if max_len == 0 {
+            return Ok(0);
+        }
+        match self.reader.read(&mut data[..max_len]) {
+            Ok(0) => Err(ChannelError::EndOfStream),
+            Ok(size) => Ok(size),
+            Err(err) => Err(channel_error(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use collections::{String, Vec};
+    use embedded_io::ErrorType;
+    use {Client, HttpHeader};
+
+    #[derive(Debug)]
+    struct FakeError;
+
+    impl Error for FakeError {
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::ConnectionReset
+        }
+    }
+
+    // Returns the data in small pieces, as a UART would.
+    struct FakeReader {
+        data: &'static [u8],
+        fail: bool,
+    }
+
+    impl ErrorType for FakeReader {
+        type Error = FakeError;
+    }
+
+    impl Read for FakeReader {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, FakeError> {
+            if self.data.is_empty() && self.fail {
+                return Err(FakeError);
+            }
+            let len = *[buf.len(), self.data.len(), 3].iter().min().unwrap();
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    // Accepts at most `max_write` bytes per write.
+    struct FakeWriter {
+        written: Vec<u8>,
+        flushed: usize,
+        max_write: usize,
+    }
+
+    impl ErrorType for FakeWriter {
+        type Error = FakeError;
+    }
+
+    impl Write for FakeWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, FakeError> {
+            let len = if buf.len() < self.max_write {
+                buf.len()
+            } else {
+                self.max_write
+            };
+            self.written.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> Result<(), FakeError> {
+            self.flushed = self.written.len();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_io_channel_get() {
+        let reader = FakeReader {
+            data: b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello",
+            fail: false,
+        };
+        let writer = FakeWriter {
+            written: Vec::new(),
+            flushed: 0,
+            max_write: 64,
+        };
+        let mut client = Client::new(IoChannel::new(reader, writer));
+        let response = client.get("http://localhost/")
+            .open()
+            .unwrap()
+            .response(|_| true)
+            .unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.headers[0],
+                   (HttpHeader::ContentLength, String::from("5")));
+
+        let mut buffer = [0u8; 16];
+        assert_eq!(response.body.read_string_to_end(&mut buffer).unwrap(), "Hello");
+
+        let writer = &response.body.writer;
+        assert_eq!(writer.written,
+                   b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec());
+        assert_eq!(writer.flushed, writer.written.len());
+    }
+
+    #[test]
+    fn test_io_channel_errors() {
+        let reader = FakeReader {
+            data: b"",
+            fail: true,
+        };
+        let writer = FakeWriter {
+            written: Vec::new(),
+            flushed: 0,
+            max_write: 0,
+        };
+        let mut channel = IoChannel::new(reader, writer);
+        assert_eq!(channel.open("localhost", 443, true).err().unwrap(),
+                   ChannelError::TlsUnsupported);
+        let mut buffer = [0u8; 4];
+        assert_eq!(channel.recv(&mut buffer, 4).err().unwrap(),
+                   ChannelError::EndOfStream);
+        // A writer that doesn't accept anything anymore.
+        assert_eq!(channel.send(b"data", 4).err().unwrap(), ChannelError::EndOfStream);
+    }
+
+    #[test]
+    fn test_io_channel_partial_writes() {
+        let reader = FakeReader {
+            data: b"HTTP/1.1 204 No Content\r\n\r\n",
+            fail: false,
+        };
+        let writer = FakeWriter {
+            written: Vec::new(),
+            flushed: 0,
+            max_write: 3,
+        };
+        let mut client = Client::new(IoChannel::new(reader, writer));
+        let response = client.post("http://localhost/upload")
+            .open()
+            .unwrap()
+            .header(HttpHeader::ContentLength, "11")
+            .unwrap()
+            .send(b"hello world")
+            .unwrap()
+            .response(|_| false)
+            .unwrap();
+        assert_eq!(response.status_code, 204);
+        assert_eq!(response.body.writer.written,
+                   b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: \
+                     11\r\n\r\nhello world"
+                       .to_vec());
+    }
+}
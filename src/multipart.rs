@@ -0,0 +1,260 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// multipart/form-data request bodies (RFC 7578), written part by part through
+// `Client::body` so that files don't have to be held in memory.
+//
+// When the size of every part is known, `content_length` gives the Content-Length header
+// to send. Otherwise, send a `Transfer-Encoding: chunked` header instead.
+
+use collections::String;
+
+use traits::{Channel, Entropy};
+use {Client, HttpError};
+
+static BOUNDARY_CHARS: &'static [u8] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+// Describes a part, to compute the body length.
+pub struct Part<'p> {
+    pub name: &'p str,
+    pub filename: Option<&'p str>,
+    pub content_type: Option<&'p str>,
+    // The length of the part data.
+    pub length: usize,
+}
+
+impl<'p> Part<'p> {
+    pub fn field(name: &'p str, value: &str) -> Self {
+        Part {
+            name: name,
+            filename: None,
+            content_type: None,
+            length: value.len(),
+        }
+    }
+
+    pub fn file(name: &'p str, filename: &'p str, content_type: &'p str, length: usize) -> Self {
+        Part {
+            name: name,
+            filename: Some(filename),
+            content_type: Some(content_type),
+            length: length,
+        }
+    }
+}
+
+// Escapes a name for a quoted string in Content-Disposition, the way browsers do.
+fn escape(value: &str) -> String {
+    let mut res = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => res.push_str("%22"),
+            '\r' => res.push_str("%0D"),
+            '\n' => res.push_str("%0A"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+pub struct MultipartWriter {
+    boundary: String,
+    parts: usize,
+}
+
+impl MultipartWriter {
+    // Uses a random boundary, unlikely to appear in the data.
+    pub fn new<E: Entropy>(entropy: &mut E) -> Self {
+        let mut random = [0u8; 24];
+        entropy.fill(&mut random);
+        let mut boundary = String::from("----FormBoundary");
+        for value in random.iter() {
+            boundary.push(BOUNDARY_CHARS[*value as usize % BOUNDARY_CHARS.len()] as char);
+        }
+        MultipartWriter::with_boundary(&boundary)
+    }
+
+    pub fn with_boundary(boundary: &str) -> Self {
+        MultipartWriter {
+            boundary: String::from(boundary),
+            parts: 0,
+        }
+    }
+
+    // The value of the Content-Type request header.
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    // The length of a body made of these parts.
+    pub fn content_length(&self, parts: &[Part]) -> usize {
+        let mut length = 0;
+        for (i, part) in parts.iter().enumerate() {
+            length += self.part_header(i == 0, part).len() + part.length;
+        }
+        length + self.end(parts.is_empty()).len()
+    }
+
+    fn part_header(&self, first: bool, part: &Part) -> String {
+        // The line end after the previous part data belongs to the delimiter.
+        let mut header = String::from(if first { "--" } else { "\r\n--" });
+        header.push_str(&self.boundary);
+        header.push_str("\r\nContent-Disposition: form-data; name=\"");
+        header.push_str(&escape(part.name));
+        header.push('"');
+        if let Some(filename) = part.filename {
+            header.push_str("; filename=\"");
+            header.push_str(&escape(filename));
+            header.push('"');
+        }
+        header.push_str("\r\n");
+        if let Some(content_type) = part.content_type {
+            // Line breaks would start other headers, or the next part.
+            header.push_str("Content-Type: ");
+            header.extend(content_type.chars().filter(|c| *c != '\r' && *c != '\n'));
+            header.push_str("\r\n");
+        }
+        header.push_str("\r\n");
+        header
+    }
+
+    fn end(&self, empty: bool) -> String {
+        format!("{}--{}--\r\n", if empty { "" } else { "\r\n" }, self.boundary)
+    }
+
+    // Starts a part. Its data is then sent with `Client::body`.
+    pub fn part<T: Channel>(&mut self, client: &mut Client<T>, part: &Part) -> Result<(), HttpError> {
+        let header = self.part_header(self.parts == 0, part);
+        self.parts += 1;
+        client.body(header.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn field<T: Channel>(&mut self,
+                             client: &mut Client<T>,
+                             name: &str,
+                             value: &str)
+                             -> Result<(), HttpError> {
+        self.part(client, &Part::field(name, value))?;
+        client.body(value.as_bytes())?;
+        Ok(())
+    }
+
+    // Starts a file part, whose data is then sent with `Client::body`.
+    pub fn file<T: Channel>(&mut self,
+                            client: &mut Client<T>,
+                            name: &str,
+                            filename: &str,
+                            content_type: &str)
+                            -> Result<(), HttpError> {
+        self.part(client, &Part::file(name, filename, content_type, 0))
+    }
+
+    // Ends the body, after which the response can be read.
+    pub fn finish<T: Channel>(&mut self, client: &mut Client<T>) -> Result<(), HttpError> {
+        let end = self.end(self.parts == 0);
+        client.send(end.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use collections::Vec;
+    use core::str;
+    use traits::MemoryChannel;
+    use HttpHeader;
+
+    struct Counter(u8);
+
+    impl Entropy for Counter {
+        fn fill(&mut self, data: &mut [u8]) {
+            for value in data.iter_mut() {
+                *value = self.0;
+                self.0 += 1;
+            }
+        }
+    }
+
+    static RESPONSE: &'static [u8] = b"HTTP/1.1 201 Created\r\n\r\n";
+
+    #[test]
+    fn test_multipart() {
+        let mut writer = MultipartWriter::new(&mut Counter(0));
+        assert_eq!(writer.content_type(),
+                   "multipart/form-data; boundary=----FormBoundary0123456789ABCDEFGHIJKLMN");
+
+        let dump = [0u8, 1, 2, 3, 4, 5];
+        let metadata = "{\"version\": \"1.2\"}";
+        let length = writer.content_length(&[Part::field("meta\"data", metadata),
+                                             Part::file("dump",
+                                                        "crash.bin",
+                                                        "application/octet-stream",
+                                                        dump.len())]);
+
+        let mut channel = MemoryChannel::new(RESPONSE);
+        {
+            let mut client = Client::new(&mut channel);
+            client.post("http://localhost/crash").open().unwrap();
+            client.header(HttpHeader::ContentType, &writer.content_type())
+                .unwrap()
+                .header(HttpHeader::ContentLength, &format!("{}", length))
+                .unwrap();
+            writer.field(&mut client, "meta\"data", metadata).unwrap();
+            writer.file(&mut client, "dump", "crash.bin", "application/octet-stream").unwrap();
+            // File data is streamed in pieces.
+            client.body(&dump[..4]).unwrap();
+            client.body(&dump[4..]).unwrap();
+            writer.finish(&mut client).unwrap();
+            assert_eq!(client.response(|_| true).unwrap().status_code, 201);
+        }
+
+        let sent = channel.sent();
+        let body_start = sent.windows(4).position(|value| value == b"\r\n\r\n").unwrap() + 4;
+        let body = &sent[body_start..];
+        assert_eq!(body.len(), length);
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(b"------FormBoundary0123456789ABCDEFGHIJKLMN\r\n\
+                                     Content-Disposition: form-data; name=\"meta%22data\"\r\n\r\n\
+                                     {\"version\": \"1.2\"}\r\n\
+                                     ------FormBoundary0123456789ABCDEFGHIJKLMN\r\n\
+                                     Content-Disposition: form-data; name=\"dump\"; \
+                                     filename=\"crash.bin\"\r\n\
+                                     Content-Type: application/octet-stream\r\n\r\n");
+        expected.extend_from_slice(&dump);
+        expected.extend_from_slice(b"\r\n------FormBoundary0123456789ABCDEFGHIJKLMN--\r\n");
+        assert_eq!(body, expected.as_slice());
+    }
+
+    #[test]
+    fn test_multipart_chunked() {
+        let mut writer = MultipartWriter::with_boundary("b");
+        let mut channel = MemoryChannel::new(RESPONSE);
+        {
+            let mut client = Client::new(&mut channel);
+            client.post("http://localhost/log").open().unwrap();
+            client.header(HttpHeader::TransferEncoding, "chunked").unwrap();
+            writer.field(&mut client, "a", "1").unwrap();
+            writer.finish(&mut client).unwrap();
+            client.response(|_| true).unwrap();
+        }
+
+        let sent = str::from_utf8(channel.sent()).unwrap();
+        assert!(sent.ends_with("\r\n\r\n31\r\n--b\r\nContent-Disposition: form-data; \
+                                name=\"a\"\r\n\r\n\r\n1\r\n1\r\n9\r\n\r\n--b--\r\n\r\n0\r\n\r\n"));
+        assert_eq!(MultipartWriter::with_boundary("b").content_length(&[]), 7);
+    }
+
+    #[test]
+    fn test_multipart_content_type() {
+        let writer = MultipartWriter::with_boundary("b");
+        let part = Part::file("f", "a.txt", "text/plain\r\n\r\n--b\r\nX-Injected: 1", 0);
+        assert_eq!(writer.part_header(true, &part),
+                   "--b\r\nContent-Disposition: form-data; name=\"f\"; \
+                    filename=\"a.txt\"\r\nContent-Type: text/plain--bX-Injected: 1\r\n\r\n");
+    }
+}
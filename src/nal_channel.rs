@@ -0,0 +1,301 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// A `Channel` implementation over any embedded-nal TCP client stack that can resolve
+// host names.
+
+use core::net::{IpAddr, SocketAddr};
+use core::str::FromStr;
+use embedded_nal::nb::block;
+use embedded_nal::{AddrType, Dns, TcpClientStack, TcpError, TcpErrorKind};
+
+use traits::{Channel, ChannelError};
+
+pub struct NalChannel<S: TcpClientStack> {
+    stack: S,
+    socket: Option<S::TcpSocket>,
+}
+
+impl<S: TcpClientStack> NalChannel<S> {
+    pub fn new(stack: S) -> Self {
+        NalChannel {
+            stack: stack,
+            socket: None,
+        }
+    }
+
+    // Closes the current connection, if any.
+    pub fn close(&mut self) -> Result<(), ChannelError> {
+        match self.socket.take() {
+            Some(socket) => {
+                self.stack.close(socket).map_err(|_| ChannelError::SomethingWentWrong)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl<S: TcpClientStack> Drop for NalChannel<S> {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+fn channel_error<E: TcpError>(err: E) -> ChannelError {
+    match err.kind() {
+        TcpErrorKind::PipeClosed => ChannelError::EndOfStream,
+        _ => ChannelError::SomethingWentWrong,
+    }
+}
+
+impl<S: TcpClientStack + Dns> Channel for NalChannel<S> {
+    fn open(&mut self, host: &str, port: u16, tls: bool) -> Result<(), ChannelError> {
+        if tls {
+            return Err(ChannelError::TlsUnsupported);
+        }
+
+        // Reopening closes any previous connection.
+        self.close()?;
+
+        // Only ask the resolver when we are not given an ip address.
+        let ip = match IpAddr::from_str(host) {
+            Ok(ip) => ip,
+            Err(_) => {
+                block!(self.stack.get_host_by_name(host, AddrType::Either))
+                    .map_err(|_| ChannelError::InvalidHostName)?
+            }
+        };
+
+        let mut socket = self.stack.socket().map_err(|_| ChannelError::UnableToConnect)?;
+        if block!(self.stack.connect(&mut socket, SocketAddr::new(ip, port))).is_err() {
+            let _ = self.stack.close(socket);
+            return Err(ChannelError::UnableToConnect);
+        }
+        self.socket = Some(socket);
+        Ok(())
+    }
+
+    // Stacks may only take part of the data, so we loop until everything is sent.
+    fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+        let socket = match self.socket {
+            Some(ref mut socket) => socket,
+            None => return Err(ChannelError::NotConnected),
+        };
+        let mut sent = 0;
+        while sent < len {
+            match block!(self.stack.send(socket, &data[sent..len])) {
+                Ok(0) => return Err(ChannelError::EndOfStream),
+                Ok(size) => sent += size,
+                Err(err) => return Err(channel_error(err)),
+            }
+        }
+        Ok(len)
+    }
+
+    fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+        let socket = match self.socket {
+            Some(ref mut socket) => socket,
+            None => return Err(ChannelError::NotConnected),
+        };
+        if max_len == 0 {
+            return Ok(0);
+        }
+        match block!(self.stack.receive(socket, &mut data[..max_len])) {
+            Ok(0) => Err(ChannelError::EndOfStream),
+            Ok(size) => Ok(size),
+            Err(err) => Err(channel_error(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use collections::{String, Vec};
+    use core::net::Ipv4Addr;
+    use embedded_nal::nb;
+    use {Client, HttpHeader};
+
+    #[derive(Debug)]
+    struct FakeError;
+
+    impl TcpError for FakeError {
+        fn kind(&self) -> TcpErrorKind {
+            TcpErrorKind::PipeClosed
+        }
+    }
+
+    // A stack with a single connection serving a canned response, and which would block
+    // once before every operation. Sends take at most `max_send` bytes.
+    struct FakeStack {
+        response: &'static [u8],
+        sent: Vec<u8>,
+        remote: Option<SocketAddr>,
+        sockets: usize,
+        would_block: bool,
+        max_send: usize,
+    }
+
+    impl FakeStack {
+        fn new(response: &'static [u8]) -> Self {
+            FakeStack {
+                response: response,
+                sent: Vec::new(),
+                remote: None,
+                sockets: 0,
+                would_block: false,
+                max_send: 1024,
+            }
+        }
+
+        fn stall(&mut self) -> bool {
+            self.would_block = !self.would_block;
+            self.would_block
+        }
+    }
+
+    impl TcpClientStack for FakeStack {
+        type TcpSocket = ();
+        type Error = FakeError;
+
+        fn socket(&mut self) -> Result<(), FakeError> {
+            self.sockets += 1;
+            Ok(())
+        }
+
+        fn connect(&mut self, _: &mut (), remote: SocketAddr) -> nb::Result<(), FakeError> {
+            if self.stall() {
+                return Err(nb::Error::WouldBlock);
+            }
+            self.remote = Some(remote);
+            Ok(())
+        }
+
+        fn send(&mut self, _: &mut (), buffer: &[u8]) -> nb::Result<usize, FakeError> {
+            if self.stall() {
+                return Err(nb::Error::WouldBlock);
+            }
+            let len = if buffer.len() < self.max_send {
+                buffer.len()
+            } else {
+                self.max_send
+            };
+            self.sent.extend_from_slice(&buffer[..len]);
+            Ok(len)
+        }
+
+        fn receive(&mut self, _: &mut (), buffer: &mut [u8]) -> nb::Result<usize, FakeError> {
+            if self.stall() {
+                return Err(nb::Error::WouldBlock);
+            }
+            if self.response.is_empty() {
+                return Err(nb::Error::Other(FakeError));
+            }
+            let len = if buffer.len() < self.response.len() {
+                buffer.len()
+            } else {
+                self.response.len()
+            };
+            buffer[..len].copy_from_slice(&self.response[..len]);
+            self.response = &self.response[len..];
+            Ok(len)
+        }
+
+        fn close(&mut self, _: ()) -> Result<(), FakeError> {
+            self.sockets -= 1;
+            Ok(())
+        }
+    }
+
+    impl Dns for FakeStack {
+        type Error = FakeError;
+
+        fn get_host_by_name(&mut self,
+                            hostname: &str,
+                            _: AddrType)
+                            -> nb::Result<IpAddr, FakeError> {
+            if self.stall() {
+                return Err(nb::Error::WouldBlock);
+            }
+            if hostname == "example.com" {
+                Ok(IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)))
+            } else {
+                Err(nb::Error::Other(FakeError))
+            }
+        }
+
+        fn get_host_by_address(&mut self, _: IpAddr, _: &mut [u8]) -> nb::Result<usize, FakeError> {
+            Err(nb::Error::Other(FakeError))
+        }
+    }
+
+    #[test]
+    fn test_nal_get() {
+        let stack = FakeStack::new(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello");
+        let mut client = Client::new(NalChannel::new(stack));
+        let response = client.get("http://example.com:8080/index.html")
+            .open()
+            .unwrap()
+            .response(|_| true)
+            .unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.headers[0],
+                   (HttpHeader::ContentLength, String::from("5")));
+
+        let mut buffer = [0u8; 16];
+        assert_eq!(response.body.read_string_to_end(&mut buffer).unwrap(), "Hello");
+
+        let channel = response.body;
+        assert_eq!(channel.stack.remote,
+                   Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)), 8080)));
+        assert_eq!(channel.stack.sent,
+                   b"GET /index.html HTTP/1.1\r\nHost: example.com\r\n\r\n".to_vec());
+        channel.close().unwrap();
+        assert_eq!(channel.stack.sockets, 0);
+    }
+
+    #[test]
+    fn test_nal_partial_sends() {
+        let mut stack = FakeStack::new(b"HTTP/1.1 204 No Content\r\n\r\n");
+        stack.max_send = 5;
+        let mut client = Client::new(NalChannel::new(stack));
+        let response = client.put("http://10.0.0.1/config")
+            .open()
+            .unwrap()
+            .header(HttpHeader::ContentLength, "12")
+            .unwrap()
+            .send(b"{\"mode\": 1}\n")
+            .unwrap()
+            .response(|_| false)
+            .unwrap();
+        assert_eq!(response.status_code, 204);
+        assert_eq!(response.body.stack.sent,
+                   b"PUT /config HTTP/1.1\r\nHost: 10.0.0.1\r\nContent-Length: \
+                     12\r\n\r\n{\"mode\": 1}\n"
+                       .to_vec());
+    }
+
+    #[test]
+    fn test_nal_open() {
+        let mut channel = NalChannel::new(FakeStack::new(b""));
+
+        // Ip addresses don't go through the resolver.
+        channel.open("10.0.0.1", 80, false).unwrap();
+        assert_eq!(channel.stack.remote,
+                   Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 80)));
+        assert_eq!(channel.stack.sockets, 1);
+
+        // Reopening closes the previous socket.
+        channel.open("10.0.0.2", 80, false).unwrap();
+        assert_eq!(channel.stack.sockets, 1);
+
+        assert_eq!(channel.open("unknown.org", 80, false).err().unwrap(),
+                   ChannelError::InvalidHostName);
+        assert_eq!(channel.stack.sockets, 0);
+        assert_eq!(channel.open("example.com", 443, true).err().unwrap(),
+                   ChannelError::TlsUnsupported);
+        assert_eq!(channel.send(b"data", 4).err().unwrap(),
+                   ChannelError::NotConnected);
+    }
+}
@@ -0,0 +1,509 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Firmware updates over the air: the image is streamed to a flash partition while its
+// SHA-256 digest is computed, and checked against an expected digest or a signed manifest.
+// The partition must only be booted once install() succeeded.
+
+use collections::{String, Vec};
+use core::cmp;
+use core::str::FromStr;
+use core::str;
+
+use base64;
+use chunked::ChunkedReader;
+use download::{Download, Sink};
+use sha256::Sha256;
+use traits::{Channel, Sleep};
+use {Client, HttpError, HttpHeader};
+
+// Manifests are small text documents.
+const MAX_MANIFEST_SIZE: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OtaError {
+    // The image doesn't fit in the storage.
+    TooLarge,
+    LengthMismatch,
+    DigestMismatch,
+    InvalidManifest,
+    BadSignature,
+    // Returned by storage implementations when erasing or writing fails.
+    Storage,
+}
+
+// Block storage such as a flash partition, erased one block at a time before being written.
+pub trait Storage {
+    // The size of erase blocks, a multiple of the page size.
+    fn block_size(&self) -> usize;
+
+    // Writes are made of whole pages, at page aligned offsets.
+    fn page_size(&self) -> usize;
+
+    fn capacity(&self) -> usize;
+
+    // Erases the block starting at `offset`.
+    fn erase(&mut self, offset: usize) -> Result<(), HttpError>;
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), HttpError>;
+}
+
+// Checks manifest signatures, eg. Ed25519 ones with the vendor's public key.
+pub trait Verifier {
+    fn verify(&mut self, message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// Writes a stream to a storage, erasing blocks ahead of the pages written, and computes the
+/// digest of the data.
+pub struct FlashWriter<'s, S: 's> {
+    storage: &'s mut S,
+    // The data of the page being filled.
+    page: Vec<u8>,
+    // Bytes written to the storage, in whole pages.
+    written: usize,
+    // The end of the erased blocks.
+    erased: usize,
+    sha256: Sha256,
+    length: usize,
+}
+
+impl<'s, S: Storage> FlashWriter<'s, S> {
+    pub fn new(storage: &'s mut S) -> Self {
+        let page_size = storage.page_size();
+        FlashWriter {
+            storage: storage,
+            page: Vec::with_capacity(page_size),
+            written: 0,
+            erased: 0,
+            sha256: Sha256::new(),
+            length: 0,
+        }
+    }
+
+    // The number of bytes received.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    fn write_page(&mut self) -> Result<(), HttpError> {
+        let end = self.written + self.page.len();
+        while self.erased < end {
+            self.storage.erase(self.erased)?;
+            self.erased += self.storage.block_size();
+        }
+        self.storage.write(self.written, &self.page)?;
+        self.written = end;
+        self.page.clear();
+        Ok(())
+    }
+
+    // Writes the last page, padded with erased bytes, and returns the digest of the data.
+    pub fn finish(mut self) -> Result<[u8; 32], HttpError> {
+        if !self.page.is_empty() {
+            let page_size = self.storage.page_size();
+            self.page.resize(page_size, 0xff);
+            self.write_page()?;
+        }
+        Ok(self.sha256.digest())
+    }
+}
+
+impl<'s, S: Storage> Sink for FlashWriter<'s, S> {
+    fn write(&mut self, data: &[u8]) -> Result<(), HttpError> {
+        if self.length + data.len() > self.storage.capacity() {
+            return Err(HttpError::Ota(OtaError::TooLarge));
+        }
+        self.sha256.update(data);
+        self.length += data.len();
+
+        let page_size = self.storage.page_size();
+        let mut data = data;
+        while !data.is_empty() {
+            let size = cmp::min(page_size - self.page.len(), data.len());
+            self.page.extend_from_slice(&data[..size]);
+            data = &data[size..];
+            if self.page.len() == page_size {
+                self.write_page()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), HttpError> {
+        self.page.clear();
+        self.written = 0;
+        self.erased = 0;
+        self.sha256 = Sha256::new();
+        self.length = 0;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Manifest {
+    pub version: String,
+    pub size: u64,
+    pub sha256: [u8; 32],
+}
+
+impl Manifest {
+    // Parses a manifest made of `name: value` lines, such as:
+    //
+    //   version: 1.4.2
+    //   size: 482304
+    //   sha256: <64 hexadecimal digits>
+    //   signature: <base64>
+    //
+    // The last line holds the signature of all the bytes before it. Unknown names are ignored.
+    pub fn parse<V: Verifier>(data: &[u8], verifier: &mut V) -> Result<Manifest, OtaError> {
+        let text = str::from_utf8(data).map_err(|_| OtaError::InvalidManifest)?;
+        let start = if text.starts_with("signature:") {
+            0
+        } else {
+            text.find("\nsignature:").ok_or(OtaError::InvalidManifest)? + 1
+        };
+        let signature = base64::decode(text[start + 10..].trim())
+            .ok_or(OtaError::InvalidManifest)?;
+        if !verifier.verify(&data[..start], &signature) {
+            return Err(OtaError::BadSignature);
+        }
+
+        let mut version = String::new();
+        let mut size = None;
+        let mut sha256 = None;
+        for line in text[..start].lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next().unwrap().trim();
+            let value = parts.next().ok_or(OtaError::InvalidManifest)?.trim();
+            if name.eq_ignore_ascii_case("version") {
+                version = String::from(value);
+            } else if name.eq_ignore_ascii_case("size") {
+                size = Some(u64::from_str(value).map_err(|_| OtaError::InvalidManifest)?);
+            } else if name.eq_ignore_ascii_case("sha256") {
+                sha256 = Some(parse_digest(value).ok_or(OtaError::InvalidManifest)?);
+            }
+        }
+        match (size, sha256) {
+            (Some(size), Some(sha256)) => {
+                Ok(Manifest {
+                    version: version,
+                    size: size,
+                    sha256: sha256,
+                })
+            }
+            _ => Err(OtaError::InvalidManifest),
+        }
+    }
+}
+
+// Parses a SHA-256 digest written in hexadecimal.
+pub fn parse_digest(value: &str) -> Option<[u8; 32]> {
+    let value = value.as_bytes();
+    if value.len() != 64 {
+        return None;
+    }
+    let mut digest = [0u8; 32];
+    for (index, pair) in value.chunks(2).enumerate() {
+        let pair = str::from_utf8(pair).ok()?;
+        if !pair.bytes().all(|value| value.is_ascii_hexdigit()) {
+            return None;
+        }
+        digest[index] = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(digest)
+}
+
+// Downloads and verifies a manifest.
+pub fn fetch_manifest<'u, T, V>(client: &mut Client<'u, T>,
+                                url: &'u str,
+                                verifier: &mut V)
+                                -> Result<Manifest, HttpError>
+    where T: Channel,
+          V: Verifier
+{
+    let response = client.get(url)
+        .open()?
+        .response(|header| {
+            header == HttpHeader::ContentLength || header == HttpHeader::TransferEncoding
+        })?;
+    if response.status_code != 200 {
+        return Err(HttpError::UnexpectedStatus(response.status_code));
+    }
+    let header = |name: HttpHeader| {
+        response.headers
+            .iter()
+            .find(|header| header.0 == name)
+            .map(|header| header.1.trim())
+    };
+    let chunked = header(HttpHeader::TransferEncoding)
+        .map_or(false, |value| value.eq_ignore_ascii_case("chunked"));
+    // Read one more byte than allowed to detect larger manifests.
+    let max_len = header(HttpHeader::ContentLength)
+        .and_then(|value| usize::from_str(value).ok())
+        .map_or(MAX_MANIFEST_SIZE + 1, |length| cmp::min(length, MAX_MANIFEST_SIZE + 1));
+
+    let mut buffer = [0u8; MAX_MANIFEST_SIZE + 1];
+    let size = if chunked {
+        ChunkedReader::new(response.body).read_to_end(&mut buffer, max_len)?
+    } else {
+        response.body.read_to_end(&mut buffer, max_len)?
+    };
+    if size > MAX_MANIFEST_SIZE {
+        return Err(HttpError::Ota(OtaError::InvalidManifest));
+    }
+    Ok(Manifest::parse(&buffer[..size], verifier)?)
+}
+
+// A firmware image to install, with its expected digest.
+pub struct Firmware<'u> {
+    download: Download<'u>,
+    sha256: [u8; 32],
+    size: Option<u64>,
+}
+
+impl<'u> Firmware<'u> {
+    pub fn new(url: &'u str, sha256: [u8; 32], size: Option<u64>) -> Self {
+        Firmware {
+            download: Download::new(url),
+            sha256: sha256,
+            size: size,
+        }
+    }
+
+    pub fn from_manifest(url: &'u str, manifest: &Manifest) -> Self {
+        Firmware::new(url, manifest.sha256, Some(manifest.size))
+    }
+
+    // The download, eg. to change its retry settings.
+    pub fn download(&mut self) -> &mut Download<'u> {
+        &mut self.download
+    }
+
+    // Writes the image to the storage, resuming after connection failures. Returns the image
+    // length once it is completely written and its digest matches. Each call writes the whole
+    // image again, as the flash writer and digest start over.
+    pub fn install<T, S, P>(&mut self,
+                            client: &mut Client<'u, T>,
+                            storage: &mut S,
+                            sleep: &mut P)
+                            -> Result<u64, HttpError>
+        where T: Channel,
+              S: Storage,
+              P: Sleep
+    {
+        if self.size.map_or(false, |size| size > storage.capacity() as u64) {
+            return Err(HttpError::Ota(OtaError::TooLarge));
+        }
+        self.download.restart();
+        let mut writer = FlashWriter::new(storage);
+        self.download.run(client, &mut writer, sleep)?;
+        let length = writer.length() as u64;
+        if self.size.map_or(false, |size| size != length) {
+            return Err(HttpError::Ota(OtaError::LengthMismatch));
+        }
+        if writer.finish()? != self.sha256 {
+            return Err(HttpError::Ota(OtaError::DigestMismatch));
+        }
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use collections::Vec;
+    use sha256::sha256;
+    use traits::{ChannelError, MemoryChannel};
+
+    struct MemoryStorage {
+        data: Vec<u8>,
+        erases: Vec<usize>,
+    }
+
+    impl MemoryStorage {
+        fn new() -> Self {
+            MemoryStorage {
+                data: vec![0; 32],
+                erases: Vec::new(),
+            }
+        }
+    }
+
+    impl Storage for MemoryStorage {
+        fn block_size(&self) -> usize {
+            8
+        }
+
+        fn page_size(&self) -> usize {
+            4
+        }
+
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+
+        fn erase(&mut self, offset: usize) -> Result<(), HttpError> {
+            assert_eq!(offset % 8, 0);
+            for value in &mut self.data[offset..offset + 8] {
+                *value = 0xff;
+            }
+            self.erases.push(offset);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), HttpError> {
+            assert_eq!(offset % 4, 0);
+            assert_eq!(data.len(), 4);
+            for (index, value) in data.iter().enumerate() {
+                // Flash writes can only clear bits.
+                assert_eq!(self.data[offset + index], 0xff);
+                self.data[offset + index] = *value;
+            }
+            Ok(())
+        }
+    }
+
+    // Signatures are the SHA-256 digest of the message.
+    struct DigestVerifier;
+
+    impl Verifier for DigestVerifier {
+        fn verify(&mut self, message: &[u8], signature: &[u8]) -> bool {
+            sha256(message)[..] == signature[..]
+        }
+    }
+
+    fn manifest(size: usize, digest: &[u8]) -> String {
+        let mut body = format!("version: 2.0.1\r\nsize: {}\r\nsha256: ", size);
+        for value in digest {
+            body.push_str(&format!("{:02x}", value));
+        }
+        body.push_str("\r\n");
+        let signature = base64::encode(&sha256(body.as_bytes()));
+        format!("{}signature: {}\r\n", body, signature)
+    }
+
+    struct NoSleep;
+
+    impl Sleep for NoSleep {
+        fn sleep_ms(&mut self, _: u32) {}
+    }
+
+    #[test]
+    fn test_flash_writer() {
+        let mut storage = MemoryStorage::new();
+        {
+            let mut writer = FlashWriter::new(&mut storage);
+            writer.write(b"01").unwrap();
+            writer.write(b"2345678").unwrap();
+            writer.write(b"9").unwrap();
+            assert_eq!(writer.length(), 10);
+            assert_eq!(writer.finish().unwrap(), sha256(b"0123456789"));
+        }
+        assert_eq!(&storage.data[..12], b"0123456789\xff\xff");
+        assert_eq!(storage.erases, [0, 8]);
+
+        let mut writer = FlashWriter::new(&mut storage);
+        assert_eq!(writer.write(&[0; 33]), Err(HttpError::Ota(OtaError::TooLarge)));
+    }
+
+    #[test]
+    fn test_manifest() {
+        let body = manifest(10, &sha256(b"0123456789"));
+        let parsed = Manifest::parse(body.as_bytes(), &mut DigestVerifier).unwrap();
+        assert_eq!(parsed,
+                   Manifest {
+                       version: String::from("2.0.1"),
+                       size: 10,
+                       sha256: sha256(b"0123456789"),
+                   });
+
+        let tampered = body.replace("size: 10", "size: 11");
+        assert_eq!(Manifest::parse(tampered.as_bytes(), &mut DigestVerifier),
+                   Err(OtaError::BadSignature));
+        assert_eq!(Manifest::parse(b"size: 10\r\n", &mut DigestVerifier),
+                   Err(OtaError::InvalidManifest));
+        assert_eq!(parse_digest("00"), None);
+        assert_eq!(parse_digest(&"0g".repeat(32)), None);
+    }
+
+    #[test]
+    fn test_install() {
+        let manifest = manifest(10, &sha256(b"0123456789"));
+        let data = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}HTTP/1.1 200 \
+                            OK\r\nContent-Length: 10\r\n\r\n0123456789",
+                           manifest.len(),
+                           manifest);
+        let mut channel = MemoryChannel::new(data.as_bytes());
+        let mut storage = MemoryStorage::new();
+        {
+            let mut client = Client::new(&mut channel);
+            let manifest =
+                fetch_manifest(&mut client, "http://localhost/manifest", &mut DigestVerifier)
+                    .unwrap();
+            assert_eq!(manifest.version, "2.0.1");
+            let mut firmware = Firmware::from_manifest("http://localhost/firmware.bin", &manifest);
+            assert_eq!(firmware.install(&mut client, &mut storage, &mut NoSleep).unwrap(),
+                       10);
+        }
+        assert_eq!(&storage.data[..10], b"0123456789");
+    }
+
+    #[test]
+    fn test_install_errors() {
+        let mut storage = MemoryStorage::new();
+
+        // The image is not the expected one.
+        let data = "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123456780";
+        let mut channel = MemoryChannel::new(data.as_bytes());
+        let mut firmware = Firmware::new("http://localhost/firmware.bin",
+                                         sha256(b"0123456789"),
+                                         None);
+        assert_eq!(firmware.install(&mut Client::new(&mut channel), &mut storage, &mut NoSleep),
+                   Err(HttpError::Ota(OtaError::DigestMismatch)));
+
+        let data = "HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n012345678";
+        let mut channel = MemoryChannel::new(data.as_bytes());
+        let mut firmware = Firmware::new("http://localhost/firmware.bin",
+                                         sha256(b"012345678"),
+                                         Some(10));
+        assert_eq!(firmware.install(&mut Client::new(&mut channel), &mut storage, &mut NoSleep),
+                   Err(HttpError::Ota(OtaError::LengthMismatch)));
+
+        // Too large for the storage, or in the middle of the download.
+        let mut firmware = Firmware::new("http://localhost/firmware.bin", [0; 32], Some(33));
+        assert_eq!(firmware.install(&mut Client::new(&mut channel), &mut storage, &mut NoSleep),
+                   Err(HttpError::Ota(OtaError::TooLarge)));
+        let data = format!("HTTP/1.1 200 OK\r\nContent-Length: 40\r\n\r\n{}", "0".repeat(40));
+        let mut channel = MemoryChannel::new(data.as_bytes());
+        let mut firmware = Firmware::new("http://localhost/firmware.bin", [0; 32], None);
+        assert_eq!(firmware.install(&mut Client::new(&mut channel), &mut storage, &mut NoSleep),
+                   Err(HttpError::Ota(OtaError::TooLarge)));
+    }
+
+    #[test]
+    fn test_install_again() {
+        let mut storage = MemoryStorage::new();
+        let mut firmware = Firmware::new("http://localhost/firmware.bin",
+                                         sha256(b"0123456789"),
+                                         Some(10));
+        firmware.download().set_max_failures(Some(0));
+
+        // The connection drops, and the next attempt fails.
+        let data = "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123";
+        let mut channel = MemoryChannel::new(data.as_bytes());
+        assert_eq!(firmware.install(&mut Client::new(&mut channel), &mut storage, &mut NoSleep),
+                   Err(HttpError::ChannelError(ChannelError::EndOfStream)));
+
+        // Installing again starts from the beginning of the image.
+        let data = "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123456789";
+        let mut channel = MemoryChannel::new(data.as_bytes());
+        assert_eq!(firmware.install(&mut Client::new(&mut channel), &mut storage, &mut NoSleep),
+                   Ok(10));
+        assert!(!str::from_utf8(channel.sent()).unwrap().contains("Range"));
+        assert_eq!(&storage.data[..10], b"0123456789");
+    }
+}
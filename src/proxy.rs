@@ -0,0 +1,96 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// HTTP proxies. Plain http requests are sent to the proxy with an absolute url, and other
+// connections go through a CONNECT tunnel (RFC 7231, section 4.3.6).
+
+use collections::String;
+
+use auth::Credentials;
+use traits::Channel;
+use {parse_status_line, HttpError, HttpHeader};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Proxy<'p> {
+    pub host: &'p str,
+    pub port: u16,
+    // Sent in the Proxy-Authorization header.
+    pub credentials: Option<Credentials<'p>>,
+}
+
+impl<'p> Proxy<'p> {
+    pub fn new(host: &'p str, port: u16) -> Self {
+        Proxy {
+            host: host,
+            port: port,
+            credentials: None,
+        }
+    }
+
+    pub fn with_credentials(self, credentials: Credentials<'p>) -> Self {
+        Proxy { credentials: Some(credentials), ..self }
+    }
+
+    // The Proxy-Authorization header line, if any.
+    pub fn authorization(&self) -> Option<String> {
+        self.credentials.map(|credentials| {
+            format!("{}{}\r\n",
+                    HttpHeader::ProxyAuthorization.as_string(),
+                    credentials.header_value())
+        })
+    }
+
+    // Opens a tunnel to host:port, on a channel connected to the proxy. Once this succeeds,
+    // the channel carries the data of the destination server.
+    pub fn connect<T: Channel>(&self,
+                               channel: &mut T,
+                               host: &str,
+                               port: u16)
+                               -> Result<(), HttpError> {
+        let authority = format!("{}:{}", host, port);
+        channel.send_str(&format!("CONNECT {} HTTP/1.1\r\n{}{}\r\n",
+                                  authority,
+                                  HttpHeader::Host.as_string(),
+                                  authority))?;
+        if let Some(authorization) = self.authorization() {
+            channel.send_str(&authorization)?;
+        }
+        channel.send_str("\r\n")?;
+
+        let mut buffer = [0u8; 256];
+        let status_line = String::from(channel.read_string_until(&mut buffer, "\r\n")?);
+        let (status_code, _) = parse_status_line(&status_line)?;
+        // The response to a successful CONNECT has no body.
+        while !channel.read_string_until(&mut buffer, "\r\n")?.is_empty() {}
+        if status_code < 200 || status_code >= 300 {
+            return Err(HttpError::UnexpectedStatus(status_code));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::str;
+    use traits::MemoryChannel;
+
+    #[test]
+    fn test_connect() {
+        let proxy = Proxy::new("proxy", 3128).with_credentials(Credentials::Basic("user", "pass"));
+        let mut channel = MemoryChannel::new(b"HTTP/1.1 200 Connection established\r\nVia: \
+                                               proxy\r\n\r\nTLS");
+        proxy.connect(&mut channel, "example.com", 443).unwrap();
+        assert_eq!(str::from_utf8(channel.sent()).unwrap(),
+                   "CONNECT example.com:443 HTTP/1.1\r\nHost: \
+                    example.com:443\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n");
+        let mut buffer = [0u8; 8];
+        assert_eq!(channel.read_string_to_end(&mut buffer).unwrap(), "TLS");
+
+        let mut channel = MemoryChannel::new(b"HTTP/1.1 407 Proxy Authentication \
+                                               Required\r\nContent-Length: 0\r\n\r\n");
+        assert_eq!(Proxy::new("proxy", 3128).connect(&mut channel, "example.com", 443),
+                   Err(HttpError::UnexpectedStatus(407)));
+    }
+}
@@ -0,0 +1,317 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Retries of requests failing with transient errors, or with statuses such as 503, after a
+// delay set by the Retry-After header or by a jittered exponential backoff.
+
+use core::cmp;
+use core::str::FromStr;
+
+use date;
+use traits::{Channel, ChannelError, Entropy, Sleep};
+use {Client, HttpError, HttpHeader, Response};
+
+// Whether an error may not happen again with another attempt.
+pub fn is_retryable_error(error: &HttpError) -> bool {
+    match *error {
+        HttpError::ChannelError(ChannelError::SomethingWentWrong) |
+        HttpError::ChannelError(ChannelError::UnableToConnect) |
+        HttpError::ChannelError(ChannelError::EndOfStream) |
+        HttpError::ChannelError(ChannelError::NotConnected) |
+        HttpError::ChannelError(ChannelError::Timeout) => true,
+        _ => false,
+    }
+}
+
+pub fn is_retryable_status(status: u16) -> bool {
+    match status {
+        408 | 429 | 500 | 502 | 503 | 504 => true,
+        _ => false,
+    }
+}
+
+// Parses a Retry-After value, either a number of seconds or an HTTP date, into milliseconds.
+// Dates need the current time.
+pub fn parse_retry_after(value: &str, now: Option<u64>) -> Option<u32> {
+    let value = value.trim();
+    let seconds = if !value.is_empty() && value.bytes().all(|value| value.is_ascii_digit()) {
+        u64::from_str(value).unwrap_or(u64::max_value())
+    } else {
+        date::parse_http_date(value)?.saturating_sub(now?)
+    };
+    Some(cmp::min(seconds, u32::max_value() as u64 / 1000) as u32 * 1000)
+}
+
+pub struct RetryPolicy {
+    max_attempts: u32,
+    // The backoff delay before the first retry, doubled for each next one, in milliseconds.
+    base_delay: u32,
+    max_delay: u32,
+    retry_all_methods: bool,
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: 500,
+            max_delay: 30000,
+            retry_all_methods: false,
+        }
+    }
+
+    // The number of attempts, including the first one.
+    pub fn set_max_attempts(&mut self, attempts: u32) {
+        self.max_attempts = cmp::max(attempts, 1);
+    }
+
+    // The backoff delays, in milliseconds. Responses asking to retry after more than the
+    // maximum delay are not retried.
+    pub fn set_delays(&mut self, base: u32, max: u32) {
+        self.base_delay = base;
+        self.max_delay = max;
+    }
+
+    // By default, only requests with idempotent methods are sent again, unless the connection
+    // couldn't even be opened.
+    pub fn set_retry_all_methods(&mut self, retry: bool) {
+        self.retry_all_methods = retry;
+    }
+
+    // The delay before a retry, after `attempt` failed ones: a random time between half and
+    // all of the exponential backoff delay.
+    pub fn backoff<E: Entropy>(&self, attempt: u32, entropy: &mut E) -> u32 {
+        let delay = if attempt >= 31 {
+            self.max_delay
+        } else {
+            cmp::min(self.base_delay as u64 * (1 << attempt), self.max_delay as u64) as u32
+        };
+        let mut random = [0u8; 4];
+        entropy.fill(&mut random);
+        let random = u32::from_le_bytes(random);
+        delay / 2 + random % (delay - delay / 2 + 1)
+    }
+
+    // Makes a request until it succeeds or can't be retried. `request` opens the request and
+    // sends its body, and `handler` gets the final response, whose headers are selected with
+    // `filter`. A response with an error status is final once all attempts are made.
+    pub fn run<'u, T, S, E, F, G, H, R>(&self,
+                                        client: &mut Client<'u, T>,
+                                        sleep: &mut S,
+                                        entropy: &mut E,
+                                        mut request: F,
+                                        filter: G,
+                                        handler: H)
+                                        -> Result<R, HttpError>
+        where T: Channel,
+              S: Sleep,
+              E: Entropy,
+              F: FnMut(&mut Client<'u, T>) -> Result<(), HttpError>,
+              G: Fn(HttpHeader) -> bool,
+              H: FnOnce(Response<T>) -> Result<R, HttpError>
+    {
+        let mut attempt = 0;
+        loop {
+            let sent = request(client);
+            let retry_method = self.retry_all_methods || client.method.is_idempotent();
+            let now = client.clock.as_mut().map(|clock| clock.now());
+            let last = attempt + 1 >= self.max_attempts;
+
+            let delay = match sent.and_then(|_| {
+                client.response(|name| {
+                    filter(name.clone()) || name == HttpHeader::RetryAfter ||
+                    name == HttpHeader::Date
+                })
+            }) {
+                Ok(mut response) => {
+                    // Retry-After dates are relative to the Date of the response.
+                    let now = response.date().or(now);
+                    let retry_after = response.headers
+                        .iter()
+                        .find(|header| header.0 == HttpHeader::RetryAfter)
+                        .and_then(|header| parse_retry_after(&header.1, now));
+                    if last || !retry_method || !is_retryable_status(response.status_code) ||
+                       retry_after.map_or(false, |delay| delay > self.max_delay) {
+                        response.headers.retain(|header| filter(header.0.clone()));
+                        return handler(response);
+                    }
+                    retry_after.unwrap_or_else(|| self.backoff(attempt, entropy))
+                }
+                Err(err) => {
+                    let connect_failed = err ==
+                                         HttpError::ChannelError(ChannelError::UnableToConnect);
+                    if last || !(retry_method || connect_failed) || !is_retryable_error(&err) {
+                        return Err(err);
+                    }
+                    self.backoff(attempt, entropy)
+                }
+            };
+            sleep.sleep_ms(delay);
+            attempt += 1;
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use collections::Vec;
+    use core::str;
+    use traits::MemoryChannel;
+
+    // Fails to connect a number of times before connecting to the inner channel.
+    struct Unreachable<'a> {
+        failures: u32,
+        channel: MemoryChannel<'a>,
+    }
+
+    impl<'a> Channel for Unreachable<'a> {
+        fn open(&mut self, host: &str, port: u16, tls: bool) -> Result<(), ChannelError> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(ChannelError::UnableToConnect);
+            }
+            self.channel.open(host, port, tls)
+        }
+
+        fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+            self.channel.send(data, len)
+        }
+
+        fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+            self.channel.recv(data, max_len)
+        }
+    }
+
+    struct Sleeps(Vec<u32>);
+
+    impl Sleep for Sleeps {
+        fn sleep_ms(&mut self, ms: u32) {
+            self.0.push(ms);
+        }
+    }
+
+    struct Fixed(u8);
+
+    impl Entropy for Fixed {
+        fn fill(&mut self, data: &mut [u8]) {
+            for value in data.iter_mut() {
+                *value = self.0;
+            }
+        }
+    }
+
+    fn get(client: &mut Client<&mut Unreachable>) -> Result<(), HttpError> {
+        client.get("http://localhost/status").open()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_after() {
+        assert_eq!(parse_retry_after("120", None), Some(120000));
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", Some(784111770)),
+                   Some(7000));
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", Some(784111780)),
+                   Some(0));
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", None), None);
+        assert_eq!(parse_retry_after("-1", None), None);
+        assert_eq!(parse_retry_after("99999999999", None), Some(4294967000));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new();
+        assert_eq!(policy.backoff(0, &mut Fixed(0)), 250);
+        assert_eq!(policy.backoff(2, &mut Fixed(0xff)), 1000 + 4294967295 % 1001);
+        assert_eq!(policy.backoff(10, &mut Fixed(0)), 15000);
+        assert_eq!(policy.backoff(40, &mut Fixed(0)), 15000);
+    }
+
+    #[test]
+    fn test_retry() {
+        let data = "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 2\r\nContent-Length: \
+                    0\r\n\r\nHTTP/1.1 503 Service Unavailable\r\nDate: Sun, 06 Nov 1994 08:49:37 \
+                    GMT\r\nRetry-After: Sun, 06 Nov 1994 08:49:40 GMT\r\n\r\nHTTP/1.1 200 \
+                    OK\r\nContent-Length: 2\r\n\r\nok";
+        let mut channel = Unreachable {
+            failures: 1,
+            channel: MemoryChannel::new(data.as_bytes()),
+        };
+        let mut sleeps = Sleeps(Vec::new());
+        let body = RetryPolicy::new()
+            .run(&mut Client::new(&mut channel),
+                 &mut sleeps,
+                 &mut Fixed(0),
+                 get,
+                 |name| name == HttpHeader::ContentLength,
+                 |response| {
+                     assert_eq!(response.status_code, 200);
+                     assert_eq!(response.headers.len(), 1);
+                     let mut buffer = [0u8; 2];
+                     response.body.read_to_end(&mut buffer, 2)?;
+                     Ok(buffer)
+                 })
+            .unwrap();
+        assert_eq!(&body, b"ok");
+        assert_eq!(sleeps.0, [250, 2000, 3000]);
+        assert_eq!(str::from_utf8(channel.channel.sent()).unwrap().matches("GET").count(), 3);
+    }
+
+    #[test]
+    fn test_no_retry() {
+        let data = "HTTP/1.1 503 Service Unavailable\r\n\r\n";
+        let mut channel = Unreachable {
+            failures: 0,
+            channel: MemoryChannel::new(data.as_bytes()),
+        };
+        let mut sleeps = Sleeps(Vec::new());
+        let mut policy = RetryPolicy::new();
+
+        // POST requests are only retried when the connection couldn't be opened.
+        let status = policy.run(&mut Client::new(&mut channel),
+                 &mut sleeps,
+                 &mut Fixed(0),
+                 |client| {
+                     client.post("http://localhost/events").open()?.send(b"{}")?;
+                     Ok(())
+                 },
+                 |_| false,
+                 |response| Ok(response.status_code))
+            .unwrap();
+        assert_eq!(status, 503);
+
+        // Out of attempts.
+        channel.failures = 3;
+        policy.set_max_attempts(3);
+        assert_eq!(policy.run(&mut Client::new(&mut channel),
+                              &mut sleeps,
+                              &mut Fixed(0),
+                              get,
+                              |_| false,
+                              |_| Ok(())),
+                   Err(HttpError::ChannelError(ChannelError::UnableToConnect)));
+        assert_eq!(sleeps.0, [250, 500]);
+
+        // Too long to wait.
+        let data = "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 3600\r\n\r\n";
+        let mut channel = Unreachable {
+            failures: 0,
+            channel: MemoryChannel::new(data.as_bytes()),
+        };
+        assert_eq!(policy.run(&mut Client::new(&mut channel),
+                              &mut sleeps,
+                              &mut Fixed(0),
+                              get,
+                              |_| false,
+                              |response| Ok(response.status_code)),
+                   Ok(429));
+        assert_eq!(sleeps.0.len(), 2);
+    }
+}
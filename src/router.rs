@@ -0,0 +1,233 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Dispatches requests to handlers based on their method and path.
+// Path patterns are made of `/` separated segments, where `:name` captures a segment and
+// a final `*` captures the rest of the path. Routes are tried in the order they were added.
+
+use collections::{String, Vec};
+
+use server::{Handler, Request, ResponseWriter};
+use traits::Channel;
+use {HttpError, HttpHeader, HttpMethod};
+
+// The segments captured by a route pattern.
+pub struct Params<'r> {
+    captures: Vec<(&'r str, &'r str)>,
+}
+
+impl<'r> Params<'r> {
+    // Returns a `:name` capture, or the rest of the path for "*".
+    pub fn get(&self, name: &str) -> Option<&'r str> {
+        self.captures.iter().find(|capture| capture.0 == name).map(|capture| capture.1)
+    }
+}
+
+pub trait RouteHandler<T: Channel> {
+    fn handle(&mut self,
+              request: &Request,
+              params: &Params,
+              response: &mut ResponseWriter<T>)
+              -> Result<(), HttpError>;
+}
+
+impl<T, F> RouteHandler<T> for F
+    where T: Channel,
+          F: FnMut(&Request, &Params, &mut ResponseWriter<T>) -> Result<(), HttpError>
+{
+    fn handle(&mut self,
+              request: &Request,
+              params: &Params,
+              response: &mut ResponseWriter<T>)
+              -> Result<(), HttpError> {
+        self(request, params, response)
+    }
+}
+
+struct Route<'a, T: 'a> {
+    method: HttpMethod,
+    pattern: &'a str,
+    handler: &'a mut dyn RouteHandler<T>,
+}
+
+pub struct Router<'a, T: 'a> {
+    routes: Vec<Route<'a, T>>,
+}
+
+impl<'a, T: Channel> Router<'a, T> {
+    pub fn new() -> Self {
+        Router { routes: Vec::new() }
+    }
+
+    // GET routes also answer HEAD requests, unless a HEAD route matches first.
+    pub fn route(&mut self,
+                 method: HttpMethod,
+                 pattern: &'a str,
+                 handler: &'a mut dyn RouteHandler<T>)
+                 -> &mut Self {
+        self.routes.push(Route {
+            method: method,
+            pattern: pattern,
+            handler: handler,
+        });
+        self
+    }
+}
+
+impl<'a, T: Channel> Default for Router<'a, T> {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+// Matches a path against a pattern, returning the captured segments.
+fn match_path<'r>(pattern: &'r str, path: &'r str) -> Option<Params<'r>> {
+    let mut captures = Vec::new();
+    let mut segments = path.split('/');
+    let mut rest = path;
+
+    for part in pattern.split('/') {
+        if part == "*" {
+            captures.push(("*", rest));
+            return Some(Params { captures: captures });
+        }
+
+        let segment = segments.next()?;
+        rest = if segment.len() < rest.len() {
+            &rest[segment.len() + 1..]
+        } else {
+            ""
+        };
+
+        if part.starts_with(':') {
+            captures.push((&part[1..], segment));
+        } else if part != segment {
+            return None;
+        }
+    }
+
+    if segments.next().is_some() {
+        return None;
+    }
+    Some(Params { captures: captures })
+}
+
+impl<'a, T: Channel> Handler<T> for Router<'a, T> {
+    fn handle(&mut self,
+              request: &Request,
+              response: &mut ResponseWriter<T>)
+              -> Result<(), HttpError> {
+        let path = request.path();
+        let mut allowed: Vec<HttpMethod> = Vec::new();
+
+        let methods: &[HttpMethod] = if request.method == HttpMethod::Head {
+            &[HttpMethod::Head, HttpMethod::Get]
+        } else {
+            &[request.method]
+        };
+
+        for method in methods {
+            for route in self.routes.iter_mut() {
+                if let Some(params) = match_path(route.pattern, path) {
+                    if route.method == *method {
+                        return route.handler.handle(request, &params, response);
+                    }
+                    if !allowed.contains(&route.method) {
+                        allowed.push(route.method);
+                    }
+                }
+            }
+        }
+
+        if allowed.is_empty() {
+            response.status(404, "Not Found")?.send(&[])?;
+            return Ok(());
+        }
+
+        if allowed.contains(&HttpMethod::Get) && !allowed.contains(&HttpMethod::Head) {
+            allowed.push(HttpMethod::Head);
+        }
+        let mut allow = String::new();
+        for method in allowed {
+            if !allow.is_empty() {
+                allow.push_str(", ");
+            }
+            allow.push_str(method.as_str());
+        }
+        response.status(405, "Method Not Allowed")?
+            .header(HttpHeader::Allow, &allow)?
+            .send(&[])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::str;
+    use server::Server;
+    use traits::MemoryChannel;
+
+    fn serve(router: &mut Router<MemoryChannel<'static>>, request: &'static str) -> String {
+        let mut channel = MemoryChannel::new(request.as_bytes());
+        Server::new().serve(&mut channel, router).unwrap();
+        String::from(str::from_utf8(channel.sent()).unwrap())
+    }
+
+    #[test]
+    fn test_match_path() {
+        let params = match_path("/api/sensors/:id", "/api/sensors/12").unwrap();
+        assert_eq!(params.get("id"), Some("12"));
+        assert_eq!(params.get("other"), None);
+
+        assert!(match_path("/api/sensors/:id", "/api/sensors").is_none());
+        assert!(match_path("/api/sensors/:id", "/api/sensors/12/name").is_none());
+        assert!(match_path("/api/config", "/api/configs").is_none());
+        assert!(match_path("/", "/").is_some());
+
+        let params = match_path("/static/:dir/*", "/static/css/site/main.css").unwrap();
+        assert_eq!(params.get("dir"), Some("css"));
+        assert_eq!(params.get("*"), Some("site/main.css"));
+        assert_eq!(match_path("/static/*", "/static/").unwrap().get("*"), Some(""));
+        assert_eq!(match_path("/static/*", "/static").unwrap().get("*"), Some(""));
+    }
+
+    #[test]
+    fn test_router() {
+        let mut sensor = |request: &Request,
+                          params: &Params,
+                          response: &mut ResponseWriter<MemoryChannel<'static>>|
+                          -> Result<(), HttpError> {
+            let body = format!("{}:{}",
+                               params.get("id").unwrap(),
+                               request.query_param("unit").unwrap_or("C"));
+            response.status(200, "OK")?.send(body.as_bytes())?;
+            Ok(())
+        };
+        let mut config = |request: &Request,
+                          _: &Params,
+                          response: &mut ResponseWriter<MemoryChannel<'static>>|
+                          -> Result<(), HttpError> {
+            response.status(200, "OK")?.send(&request.body)?;
+            Ok(())
+        };
+
+        let mut router = Router::new();
+        router.route(HttpMethod::Get, "/api/sensors/:id", &mut sensor)
+            .route(HttpMethod::Put, "/api/config", &mut config);
+
+        assert_eq!(serve(&mut router, "GET /api/sensors/3?unit=F HTTP/1.1\r\n\r\n"),
+                   "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\n3:F");
+        assert_eq!(serve(&mut router, "HEAD /api/sensors/3 HTTP/1.1\r\n\r\n"),
+                   "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\n");
+        assert_eq!(serve(&mut router,
+                         "PUT /api/config HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}"),
+                   "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}");
+        assert_eq!(serve(&mut router, "GET /api/other HTTP/1.1\r\n\r\n"),
+                   "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        assert_eq!(serve(&mut router, "DELETE /api/sensors/3 HTTP/1.1\r\n\r\n"),
+                   "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET, HEAD\r\nContent-Length: \
+                    0\r\n\r\n");
+    }
+}
@@ -0,0 +1,526 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// The server side: reads a request from a channel, hands it over to a `Handler` and
+// writes the response back. Accepting connections is up to the transport layer, and
+// each call to `Server::serve` handles a single request.
+
+use collections::{String, Vec};
+use core::str::FromStr;
+
+use chunked::ChunkedReader;
+use traits::{Channel, ChannelError};
+use urlencoded;
+use {HttpError, HttpHeader, HttpMethod, LINE_END};
+
+// Maximum number of headers we accept in a request.
+const MAX_HEADERS: usize = 32;
+
+pub struct Request {
+    pub method: HttpMethod,
+    // The path and query of the request.
+    pub target: String,
+    pub headers: Vec<(HttpHeader, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn path(&self) -> &str {
+        match self.target.find('?') {
+            Some(pos) => &self.target[..pos],
+            None => &self.target,
+        }
+    }
+
+    // The query string, without the leading `?`.
+    pub fn query(&self) -> Option<&str> {
+        self.target.find('?').map(|pos| &self.target[pos + 1..])
+    }
+
+    // Returns the raw value of a query parameter, or an empty string if it has none.
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query().and_then(|query| {
+            query.split('&')
+                .map(|param| match param.find('=') {
+                    Some(pos) => (&param[..pos], &param[pos + 1..]),
+                    None => (param, ""),
+                })
+                .find(|param| param.0 == name)
+                .map(|param| param.1)
+        })
+    }
+
+    // Returns the decoded value of the first query parameter with this decoded name, or an
+    // empty string if it has none.
+    pub fn decoded_query_param(&self, name: &str) -> Option<String> {
+        self.query_params().into_iter().find(|param| param.0 == name).map(|param| param.1)
+    }
+
+    // The decoded query parameters.
+    pub fn query_params(&self) -> Vec<(String, String)> {
+        self.query().map_or(Vec::new(), urlencoded::decode_pairs)
+    }
+
+    // Returns the value of the first header with this name, compared case insensitively.
+    pub fn header(&self, name: &HttpHeader) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.0.name().eq_ignore_ascii_case(name.name()))
+            .map(|header| header.1.as_str())
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+enum WriterState {
+    Status,
+    Headers,
+    Body,
+}
+
+pub struct ResponseWriter<'a, T: 'a> {
+    channel: &'a mut T,
+    state: WriterState,
+    // Responses to HEAD requests don't have a body.
+    head: bool,
+    has_length: bool,
+}
+
+impl<'a, T: Channel> ResponseWriter<'a, T> {
+    pub fn new(channel: &'a mut T, method: HttpMethod) -> Self {
+        ResponseWriter {
+            channel: channel,
+            state: WriterState::Status,
+            head: method == HttpMethod::Head,
+            has_length: false,
+        }
+    }
+
+    pub fn status(&mut self, code: u16, reason: &str) -> Result<&mut Self, HttpError> {
+        assert_eq!(self.state, WriterState::Status);
+
+        self.channel.send_str(&format!("HTTP/1.1 {} {}", code, reason))?;
+        self.channel.send_str(LINE_END)?;
+
+        self.state = WriterState::Headers;
+        Ok(self)
+    }
+
+    pub fn headers(&mut self, headers: &[(HttpHeader, &str)]) -> Result<&mut Self, HttpError> {
+        assert_eq!(self.state, WriterState::Headers);
+
+        for header in headers {
+            if header.0 == HttpHeader::ContentLength {
+                self.has_length = true;
+            }
+            self.channel.send_str(&header.0.as_string())?;
+            self.channel.send_str(header.1)?;
+            self.channel.send_str(LINE_END)?;
+        }
+
+        Ok(self)
+    }
+
+    pub fn header(&mut self, name: HttpHeader, value: &str) -> Result<&mut Self, HttpError> {
+        self.headers(&[(name, value)])
+    }
+
+    // Sends a part of the body. Can be called multiple times before a send().
+    // Without a Content-Length header, the end of the body is the end of the connection.
+    pub fn body(&mut self, body: &[u8]) -> Result<&mut Self, HttpError> {
+        if self.state == WriterState::Headers {
+            self.channel.send_str(LINE_END)?;
+            self.state = WriterState::Body;
+        }
+
+        assert_eq!(self.state, WriterState::Body);
+        if !self.head && !body.is_empty() {
+            self.channel.send(body, body.len())?;
+        }
+
+        Ok(self)
+    }
+
+    // Last or single send of a sequence. The Content-Length header is added if the headers
+    // were not sent yet and don't include it. For HEAD requests, an empty body doesn't tell
+    // the length of the GET one, so it is left out.
+    pub fn send(&mut self, body: &[u8]) -> Result<&mut Self, HttpError> {
+        if self.state == WriterState::Headers && !self.has_length &&
+           !(self.head && body.is_empty()) {
+            self.header(HttpHeader::ContentLength, &format!("{}", body.len()))?;
+        }
+        self.body(body)
+    }
+
+    // The underlying channel, eg. to switch protocols after a 101 response.
+    pub fn channel(&mut self) -> &mut T {
+        self.channel
+    }
+
+    // Whether the status line has been sent.
+    pub fn is_started(&self) -> bool {
+        self.state != WriterState::Status
+    }
+}
+
+pub trait Handler<T: Channel> {
+    fn handle(&mut self,
+              request: &Request,
+              response: &mut ResponseWriter<T>)
+              -> Result<(), HttpError>;
+}
+
+impl<T, F> Handler<T> for F
+    where T: Channel,
+          F: FnMut(&Request, &mut ResponseWriter<T>) -> Result<(), HttpError>
+{
+    fn handle(&mut self,
+              request: &Request,
+              response: &mut ResponseWriter<T>)
+              -> Result<(), HttpError> {
+        self(request, response)
+    }
+}
+
+// The error response matching a request parsing error, if the client is still there to
+// receive it.
+fn error_status(error: &HttpError) -> Option<(u16, &'static str)> {
+    match *error {
+        HttpError::InvalidRequest => Some((400, "Bad Request")),
+        HttpError::BodyTooLarge => Some((413, "Payload Too Large")),
+        HttpError::UriTooLong => Some((414, "URI Too Long")),
+        HttpError::ChannelError(ChannelError::BufferFull) => {
+            Some((431, "Request Header Fields Too Large"))
+        }
+        HttpError::UnsupportedMethod => Some((501, "Not Implemented")),
+        HttpError::InvalidVersion => Some((505, "HTTP Version Not Supported")),
+        _ => None,
+    }
+}
+
+pub struct Server {
+    max_body_size: usize,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server { max_body_size: 4096 }
+    }
+
+    // Requests with a larger body are rejected.
+    pub fn set_max_body_size(&mut self, size: usize) {
+        self.max_body_size = size;
+    }
+
+    // Reads a request and lets the handler respond to it. Invalid requests get an error
+    // response, and so do handlers that fail before sending anything.
+    pub fn serve<T, H>(&self, channel: &mut T, handler: &mut H) -> Result<(), HttpError>
+        where T: Channel,
+              H: Handler<T>
+    {
+        let request = match self.read_request(channel) {
+            Ok(request) => request,
+            Err(err) => {
+                if let Some((code, reason)) = error_status(&err) {
+                    ResponseWriter::new(channel, HttpMethod::Get).status(code, reason)?
+                        .header(HttpHeader::Connection, "close")?
+                        .send(&[])?;
+                }
+                return Err(err);
+            }
+        };
+
+        let mut response = ResponseWriter::new(channel, request.method);
+        let res = handler.handle(&request, &mut response);
+        if !response.is_started() {
+            response.status(500, "Internal Server Error")?.send(&[])?;
+        }
+        res
+    }
+
+    pub fn read_request<T: Channel>(&self, channel: &mut T) -> Result<Request, HttpError> {
+        let mut buffer = [0u8; 1024];
+
+        let line = channel.read_string_until(&mut buffer, "\r\n").map_err(|err| match err {
+            ChannelError::BufferFull => HttpError::UriTooLong,
+            err => HttpError::from(err),
+        })?;
+        let (method, target) = parse_request_line(line)?;
+
+        let mut headers = Vec::new();
+        loop {
+            let line = channel.read_string_until(&mut buffer, "\r\n")?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(HttpError::from(ChannelError::BufferFull));
+            }
+            headers.push(parse_request_header(line)?);
+        }
+
+        let mut request = Request {
+            method: method,
+            target: target,
+            headers: headers,
+            body: Vec::new(),
+        };
+
+        if let Some(encoding) = request.header(&HttpHeader::TransferEncoding) {
+            // With both, a proxy in front of us could frame the body differently.
+            if !encoding.eq_ignore_ascii_case("chunked") ||
+               request.header(&HttpHeader::ContentLength).is_some() {
+                return Err(HttpError::InvalidRequest);
+            }
+            request.body = self.read_chunked_body(channel)?;
+        } else if let Some(length) = request.header(&HttpHeader::ContentLength) {
+            // Several lengths could be understood differently by a proxy in front of us.
+            let count = request.headers
+                .iter()
+                .filter(|header| header.0.name().eq_ignore_ascii_case("Content-Length"))
+                .count();
+            // from_str accepts a sign.
+            if count > 1 || length.is_empty() ||
+               !length.bytes().all(|value| value.is_ascii_digit()) {
+                return Err(HttpError::InvalidRequest);
+            }
+            let length = usize::from_str(length).map_err(|_| HttpError::InvalidRequest)?;
+            if length > self.max_body_size {
+                return Err(HttpError::BodyTooLarge);
+            }
+            request.body = read_body(channel, length)?;
+        }
+
+        Ok(request)
+    }
+
+    fn read_chunked_body<T: Channel>(&self, channel: &mut T) -> Result<Vec<u8>, HttpError> {
+        let mut reader = ChunkedReader::new(channel);
+        let mut body = Vec::new();
+        let mut buffer = [0u8; 256];
+        loop {
+            let max_len = buffer.len();
+            match reader.recv(&mut buffer, max_len) {
+                Ok(size) => body.extend_from_slice(&buffer[..size]),
+                Err(ChannelError::EndOfStream) => return Ok(body),
+                Err(err) => return Err(HttpError::from(err)),
+            }
+            if body.len() > self.max_body_size {
+                return Err(HttpError::BodyTooLarge);
+            }
+        }
+    }
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server::new()
+    }
+}
+
+fn parse_request_line(line: &str) -> Result<(HttpMethod, String), HttpError> {
+    let mut parts = line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) => (method, target, version),
+        _ => return Err(HttpError::InvalidRequest),
+    };
+
+    if !version.starts_with("HTTP/") {
+        return Err(HttpError::InvalidRequest);
+    }
+    if version != "HTTP/1.0" && version != "HTTP/1.1" {
+        return Err(HttpError::InvalidVersion);
+    }
+
+    if !target.starts_with('/') {
+        return Err(HttpError::InvalidRequest);
+    }
+
+    let method = HttpMethod::from_str(method).map_err(|_| HttpError::UnsupportedMethod)?;
+    Ok((method, String::from(target)))
+}
+
+fn parse_request_header(line: &str) -> Result<(HttpHeader, String), HttpError> {
+    let pos = line.find(':').ok_or(HttpError::InvalidRequest)?;
+    let name = &line[..pos];
+    // No whitespace is allowed in the name, nor between the name and the colon.
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace()) {
+        return Err(HttpError::InvalidRequest);
+    }
+    let value = line[pos + 1..].trim();
+    Ok((HttpHeader::from_name(name), String::from(value)))
+}
+
+fn read_body<T: Channel>(channel: &mut T, length: usize) -> Result<Vec<u8>, HttpError> {
+    let mut body = vec![0u8; length];
+    let mut pos = 0;
+    while pos < length {
+        pos += channel.recv(&mut body[pos..], length - pos)?;
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use collections::String;
+    use core::str;
+    use traits::MemoryChannel;
+
+    // Echoes the request back.
+    struct Echo;
+
+    impl<T: Channel> Handler<T> for Echo {
+        fn handle(&mut self,
+                  request: &Request,
+                  response: &mut ResponseWriter<T>)
+                  -> Result<(), HttpError> {
+            let content_type = request.header(&HttpHeader::ContentType).unwrap_or("text/plain");
+            response.status(200, "OK")?
+                .header(HttpHeader::ContentType, content_type)?
+                .header(HttpHeader::Other(String::from("X-Path:")), request.path())?
+                .send(&request.body)?;
+            Ok(())
+        }
+    }
+
+    fn serve<H: Handler<MemoryChannel<'static>>>(request: &'static str,
+                                                 handler: &mut H)
+                                                 -> (Result<(), HttpError>, String) {
+        let mut channel = MemoryChannel::new(request.as_bytes());
+        let res = Server::new().serve(&mut channel, handler);
+        (res, String::from(str::from_utf8(channel.sent()).unwrap()))
+    }
+
+    #[test]
+    fn test_serve_get() {
+        let (res, response) = serve("GET /status?verbose=1 HTTP/1.1\r\nHost: device\r\n\r\n",
+                                    &mut Echo);
+        res.unwrap();
+        assert_eq!(response,
+                   "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nX-Path: /status\r\n\
+                    Content-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_serve_post() {
+        let (res, response) = serve("POST /config HTTP/1.1\r\ncontent-type: \
+                                     application/json\r\nContent-Length: 11\r\n\r\n{\"a\": true}",
+                                    &mut Echo);
+        res.unwrap();
+        assert_eq!(response,
+                   "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nX-Path: \
+                    /config\r\nContent-Length: 11\r\n\r\n{\"a\": true}");
+
+        let (res, response) = serve("PUT /config HTTP/1.1\r\nTransfer-Encoding: \
+                                     chunked\r\n\r\n4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n",
+                                    &mut Echo);
+        res.unwrap();
+        assert!(response.ends_with("Content-Length: 9\r\n\r\nWikipedia"));
+    }
+
+    #[test]
+    fn test_serve_head() {
+        let (res, response) = serve("HEAD / HTTP/1.0\r\n\r\n", &mut Echo);
+        res.unwrap();
+        assert_eq!(response,
+                   "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nX-Path: /\r\n\r\n");
+
+        // The length of the body passed by the handler is kept, without the body.
+        let mut handler = |_: &Request, response: &mut ResponseWriter<MemoryChannel<'static>>| {
+            response.status(200, "OK")?.send(b"Hello")?;
+            Ok(())
+        };
+        let (res, response) = serve("HEAD / HTTP/1.1\r\n\r\n", &mut handler);
+        res.unwrap();
+        assert_eq!(response, "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n");
+    }
+
+    #[test]
+    fn test_serve_closure() {
+        let mut count = 0;
+        {
+            let mut handler = |request: &Request,
+                               response: &mut ResponseWriter<MemoryChannel<'static>>|
+                               -> Result<(), HttpError> {
+                count += 1;
+                assert_eq!(request.query(), Some("a=b+c&d%20e=f%26&g"));
+                assert_eq!(request.query_params(),
+                           [(String::from("a"), String::from("b c")),
+                            (String::from("d e"), String::from("f&")),
+                            (String::from("g"), String::new())]);
+                assert_eq!(request.query_param("a"), Some("b+c"));
+                assert_eq!(request.decoded_query_param("a").unwrap(), "b c");
+                assert_eq!(request.decoded_query_param("d e").unwrap(), "f&");
+                assert_eq!(request.decoded_query_param("g").unwrap(), "");
+                assert_eq!(request.decoded_query_param("h"), None);
+                response.status(204, "No Content")?.body(&[])?;
+                Ok(())
+            };
+            let (res, response) = serve("DELETE /item?a=b+c&d%20e=f%26&g HTTP/1.1\r\n\r\n",
+                                        &mut handler);
+            res.unwrap();
+            assert_eq!(response, "HTTP/1.1 204 No Content\r\n\r\n");
+        }
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_serve_errors() {
+        let (res, response) = serve("GET /\r\n\r\n", &mut Echo);
+        assert_eq!(res.err().unwrap(), HttpError::InvalidRequest);
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        let (res, response) = serve("GET / HTTP/2.0\r\n\r\n", &mut Echo);
+        assert_eq!(res.err().unwrap(), HttpError::InvalidVersion);
+        assert!(response.starts_with("HTTP/1.1 505 "));
+
+        let (res, response) = serve("PATCH / HTTP/1.1\r\n\r\n", &mut Echo);
+        assert_eq!(res.err().unwrap(), HttpError::UnsupportedMethod);
+        assert!(response.starts_with("HTTP/1.1 501 "));
+
+        let (res, response) = serve("POST / HTTP/1.1\r\nContent-Length: 10000\r\n\r\n",
+                                    &mut Echo);
+        assert_eq!(res.err().unwrap(), HttpError::BodyTooLarge);
+        assert!(response.starts_with("HTTP/1.1 413 "));
+
+        let (res, response) = serve("GET / HTTP/1.1\r\nHost : device\r\n\r\n", &mut Echo);
+        assert_eq!(res.err().unwrap(), HttpError::InvalidRequest);
+        assert!(response.starts_with("HTTP/1.1 400 "));
+
+        // Duplicate, conflicting and signed lengths.
+        for request in &["POST / HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 2\r\n\r\nok",
+                         "POST / HTTP/1.1\r\nContent-Length: 2\r\ncontent-length: 3\r\n\r\nok",
+                         "POST / HTTP/1.1\r\nContent-Length: 2, 3\r\n\r\nok",
+                         "POST / HTTP/1.1\r\nContent-Length: +2\r\n\r\nok",
+                         "POST / HTTP/1.1\r\nContent-Length:\r\n\r\nok",
+                         "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: \
+                          2\r\n\r\n2\r\nok\r\n0\r\n\r\n"] {
+            let (res, response) = serve(request, &mut Echo);
+            assert_eq!(res.err().unwrap(), HttpError::InvalidRequest);
+            assert!(response.starts_with("HTTP/1.1 400 "));
+        }
+
+        // The request line doesn't fit, as opposed to a header.
+        let request = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(2000));
+        let mut channel = MemoryChannel::new(request.as_bytes());
+        assert_eq!(Server::new().serve(&mut channel, &mut Echo).err().unwrap(),
+                   HttpError::UriTooLong);
+        assert!(channel.sent().starts_with(b"HTTP/1.1 414 "));
+
+        // The client went away, there is nobody to answer to.
+        let (res, response) = serve("GET / HTTP/1.1\r\nHost: dev", &mut Echo);
+        assert_eq!(res.err().unwrap(),
+                   HttpError::ChannelError(ChannelError::EndOfStream));
+        assert_eq!(response, "");
+
+        // Handlers failing without answering get a 500.
+        let mut failing = |_: &Request, _: &mut ResponseWriter<MemoryChannel<'static>>| {
+            Err(HttpError::UnknownError)
+        };
+        let (res, response) = serve("GET / HTTP/1.1\r\n\r\n", &mut failing);
+        assert_eq!(res.err().unwrap(), HttpError::UnknownError);
+        assert_eq!(response,
+                   "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n");
+    }
+}
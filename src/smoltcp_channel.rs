@@ -0,0 +1,329 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// A `Channel` implementation over a smoltcp TCP socket.
+// smoltcp doesn't run in the background, so the channel polls the interface itself
+// whenever it has to wait on the socket.
+
+use core::str::FromStr;
+use smoltcp::iface::{Interface, SocketHandle, SocketSet};
+use smoltcp::phy::Device;
+use smoltcp::socket::tcp;
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::IpAddress;
+
+use traits::{Channel, ChannelError};
+
+// Start of the dynamic ports range.
+const FIRST_LOCAL_PORT: u16 = 49152;
+
+pub trait Driver {
+    // Current time, used to drive the interface timers.
+    fn now(&mut self) -> Instant;
+
+    // Called every time the interface was polled without the channel being able to make
+    // progress. This is the place to service other sockets, or to sleep for at most `delay`
+    // (None meaning until the next packet arrives).
+    fn idle(&mut self, _sockets: &mut SocketSet, _delay: Option<Duration>) {}
+}
+
+pub struct SmoltcpChannel<'a, 's: 'a, D: Device + 'a, E: Driver> {
+    iface: &'a mut Interface,
+    device: &'a mut D,
+    sockets: &'a mut SocketSet<'s>,
+    handle: SocketHandle,
+    driver: E,
+    timeout: Duration,
+    local_port: u16,
+}
+
+impl<'a, 's, D: Device, E: Driver> SmoltcpChannel<'a, 's, D, E> {
+    // `handle` is a TCP socket in `sockets`, that this channel will use for all its
+    // connections.
+    pub fn new(iface: &'a mut Interface,
+               device: &'a mut D,
+               sockets: &'a mut SocketSet<'s>,
+               handle: SocketHandle,
+               driver: E)
+               -> Self {
+        SmoltcpChannel {
+            iface: iface,
+            device: device,
+            sockets: sockets,
+            handle: handle,
+            driver: driver,
+            timeout: Duration::from_secs(30),
+            local_port: FIRST_LOCAL_PORT,
+        }
+    }
+
+    // Sets how long we wait for the socket to be ready before giving up.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn driver(&mut self) -> &mut E {
+        &mut self.driver
+    }
+
+    // Gracefully closes the connection, waiting for the remote side to acknowledge it.
+    pub fn close(&mut self) -> Result<(), ChannelError> {
+        self.sockets.get_mut::<tcp::Socket>(self.handle).close();
+        let res = self.wait(|socket| match socket.state() {
+            tcp::State::Closed | tcp::State::TimeWait => Some(Ok(())),
+            _ => None,
+        });
+        if res.is_err() {
+            self.sockets.get_mut::<tcp::Socket>(self.handle).abort();
+        }
+        res
+    }
+
+    // Polls the interface until `ready` returns a result for the socket, or we time out.
+    fn wait<F>(&mut self, ready: F) -> Result<(), ChannelError>
+        where F: Fn(&tcp::Socket) -> Option<Result<(), ChannelError>>
+    {
+        let start = self.driver.now();
+        loop {
+            let now = self.driver.now();
+            self.iface.poll(now, &mut *self.device, self.sockets);
+
+            if let Some(res) = ready(self.sockets.get::<tcp::Socket>(self.handle)) {
+                return res;
+            }
+
+            if now - start >= self.timeout {
+                return Err(ChannelError::Timeout);
+            }
+
+            let delay = self.iface.poll_delay(now, self.sockets);
+            self.driver.idle(self.sockets, delay);
+        }
+    }
+
+    fn next_local_port(&mut self) -> u16 {
+        let port = self.local_port;
+        self.local_port = port.checked_add(1).unwrap_or(FIRST_LOCAL_PORT);
+        port
+    }
+}
+
+impl<'a, 's, D: Device, E: Driver> Channel for SmoltcpChannel<'a, 's, D, E> {
+    // There is no resolver here, so `host` has to be an ip address.
+    fn open(&mut self, host: &str, port: u16, tls: bool) -> Result<(), ChannelError> {
+        if tls {
+            return Err(ChannelError::TlsUnsupported);
+        }
+
+        let address = IpAddress::from_str(host).map_err(|_| ChannelError::InvalidHostName)?;
+        let local_port = self.next_local_port();
+
+        {
+            let socket = self.sockets.get_mut::<tcp::Socket>(self.handle);
+            // Reopening drops any previous connection.
+            if socket.is_open() {
+                socket.abort();
+            }
+            socket.connect(self.iface.context(), (address, port), local_port)
+                .map_err(|_| ChannelError::UnableToConnect)?;
+        }
+
+        self.wait(|socket| if socket.may_send() {
+            Some(Ok(()))
+        } else if !socket.is_active() {
+            // We got reset.
+            Some(Err(ChannelError::UnableToConnect))
+        } else {
+            None
+        })
+    }
+
+    // Only what fits in the transmit buffer can be queued at once, so we keep polling the
+    // interface until all the data is queued.
+    fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+        let mut sent = 0;
+        while sent < len {
+            if !self.sockets.get::<tcp::Socket>(self.handle).can_send() {
+                self.wait(|socket| if socket.can_send() {
+                    Some(Ok(()))
+                } else if !socket.may_send() {
+                    Some(Err(ChannelError::EndOfStream))
+                } else {
+                    None
+                })?;
+            }
+
+            // The data is sent when the interface is next polled.
+            sent += self.sockets
+                .get_mut::<tcp::Socket>(self.handle)
+                .send_slice(&data[sent..len])
+                .map_err(|_| ChannelError::NotConnected)?;
+        }
+        Ok(len)
+    }
+
+    fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+        if max_len == 0 {
+            return Ok(0);
+        }
+
+        if !self.sockets.get::<tcp::Socket>(self.handle).can_recv() {
+            self.wait(|socket| if socket.can_recv() {
+                Some(Ok(()))
+            } else if !socket.may_recv() {
+                Some(Err(ChannelError::EndOfStream))
+            } else {
+                None
+            })?;
+        }
+
+        self.sockets
+            .get_mut::<tcp::Socket>(self.handle)
+            .recv_slice(&mut data[..max_len])
+            .map_err(|_| ChannelError::EndOfStream)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use collections::{String, Vec};
+    use smoltcp::iface::Config;
+    use smoltcp::phy::{Loopback, Medium};
+    use smoltcp::wire::{HardwareAddress, IpCidr};
+    use {Client, HttpHeader};
+
+    // Simulated time, and a HTTP server stand-in running on the same interface.
+    struct TestDriver {
+        time: Instant,
+        server: SocketHandle,
+        request: Vec<u8>,
+        response: &'static str,
+    }
+
+    impl Driver for TestDriver {
+        fn now(&mut self) -> Instant {
+            self.time += Duration::from_millis(10);
+            self.time
+        }
+
+        fn idle(&mut self, sockets: &mut SocketSet, _: Option<Duration>) {
+            let socket = sockets.get_mut::<tcp::Socket>(self.server);
+            if socket.can_recv() {
+                let request = &mut self.request;
+                socket.recv(|data| {
+                        request.extend_from_slice(data);
+                        (data.len(), ())
+                    })
+                    .unwrap();
+                if request.ends_with(b"\r\n\r\n") {
+                    socket.send_slice(self.response.as_bytes()).unwrap();
+                    socket.close();
+                }
+            }
+        }
+    }
+
+    fn tcp_socket<'a>(tx_size: usize) -> tcp::Socket<'a> {
+        tcp::Socket::new(tcp::SocketBuffer::new(vec![0; 1024]),
+                         tcp::SocketBuffer::new(vec![0; tx_size]))
+    }
+
+    fn loopback() -> (Interface, Loopback) {
+        let mut device = Loopback::new(Medium::Ip);
+        let mut iface = Interface::new(Config::new(HardwareAddress::Ip),
+                                       &mut device,
+                                       Instant::ZERO);
+        iface.update_ip_addrs(|addrs| {
+            addrs.push(IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8)).unwrap();
+        });
+        (iface, device)
+    }
+
+    #[test]
+    fn test_smoltcp_get() {
+        let (mut iface, mut device) = loopback();
+        let mut sockets = SocketSet::new(vec![]);
+
+        let mut server = tcp_socket(1024);
+        server.listen(8080).unwrap();
+        let server = sockets.add(server);
+        let client = sockets.add(tcp_socket(1024));
+
+        let driver = TestDriver {
+            time: Instant::ZERO,
+            server: server,
+            request: Vec::new(),
+            response: "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello",
+        };
+        let channel = SmoltcpChannel::new(&mut iface, &mut device, &mut sockets, client, driver);
+        let mut client = Client::new(channel);
+        let response = client.get("http://127.0.0.1:8080/status")
+            .open()
+            .unwrap()
+            .response(|_| true)
+            .unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.headers[0],
+                   (HttpHeader::ContentLength, String::from("5")));
+
+        let mut buffer = [0u8; 16];
+        assert_eq!(response.body.read_string_to_end(&mut buffer).unwrap(), "Hello");
+        response.body.close().unwrap();
+        assert_eq!(response.body.driver().request,
+                   b"GET /status HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_smoltcp_small_tx_buffer() {
+        let (mut iface, mut device) = loopback();
+        let mut sockets = SocketSet::new(vec![]);
+
+        let mut server = tcp_socket(1024);
+        server.listen(8080).unwrap();
+        let server = sockets.add(server);
+        let client = sockets.add(tcp_socket(16));
+
+        let driver = TestDriver {
+            time: Instant::ZERO,
+            server: server,
+            request: Vec::new(),
+            response: "HTTP/1.1 204 No Content\r\n\r\n",
+        };
+        let channel = SmoltcpChannel::new(&mut iface, &mut device, &mut sockets, client, driver);
+        let mut client = Client::new(channel);
+        let url = "http://127.0.0.1:8080/a/path/much/longer/than/the/transmit/buffer";
+        let response = client.get(url).open().unwrap().response(|_| false).unwrap();
+        assert_eq!(response.status_code, 204);
+        assert_eq!(response.body.driver().request,
+                   b"GET /a/path/much/longer/than/the/transmit/buffer HTTP/1.1\r\nHost: \
+                     127.0.0.1\r\n\r\n"
+                       .to_vec());
+    }
+
+    #[test]
+    fn test_smoltcp_open_errors() {
+        let (mut iface, mut device) = loopback();
+        let mut sockets = SocketSet::new(vec![]);
+        let server = sockets.add(tcp_socket(1024));
+        let client = sockets.add(tcp_socket(1024));
+
+        let driver = TestDriver {
+            time: Instant::ZERO,
+            server: server,
+            request: Vec::new(),
+            response: "",
+        };
+        let mut channel =
+            SmoltcpChannel::new(&mut iface, &mut device, &mut sockets, client, driver);
+
+        // Nobody is listening, we are reset.
+        assert_eq!(channel.open("127.0.0.1", 8080, false).err().unwrap(),
+                   ChannelError::UnableToConnect);
+        assert_eq!(channel.open("localhost", 8080, false).err().unwrap(),
+                   ChannelError::InvalidHostName);
+        assert_eq!(channel.open("127.0.0.1", 443, true).err().unwrap(),
+                   ChannelError::TlsUnsupported);
+    }
+}
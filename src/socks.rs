@@ -0,0 +1,328 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// A Channel connecting through a SOCKS5 proxy (RFC 1928), with optional username/password
+// authentication (RFC 1929). Host names are resolved by the proxy.
+
+use collections::Vec;
+
+use traits::{Channel, ChannelError};
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const CONNECT: u8 = 1;
+const ADDRESS_IPV4: u8 = 1;
+const ADDRESS_DOMAIN: u8 = 3;
+const ADDRESS_IPV6: u8 = 4;
+
+pub struct Socks5Channel<'p, T> {
+    channel: T,
+    proxy_host: &'p str,
+    proxy_port: u16,
+    credentials: Option<(&'p str, &'p str)>,
+}
+
+impl<'p, T: Channel> Socks5Channel<'p, T> {
+    pub fn new(channel: T, proxy_host: &'p str, proxy_port: u16) -> Self {
+        Socks5Channel {
+            channel: channel,
+            proxy_host: proxy_host,
+            proxy_port: proxy_port,
+            credentials: None,
+        }
+    }
+
+    // Offers username/password authentication to the proxy.
+    pub fn with_credentials(self, user: &'p str, password: &'p str) -> Self {
+        Socks5Channel { credentials: Some((user, password)), ..self }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.channel
+    }
+
+    fn authenticate(&mut self, user: &str, password: &str) -> Result<(), ChannelError> {
+        if user.len() > 255 || password.len() > 255 {
+            return Err(ChannelError::UnableToConnect);
+        }
+        let mut request = Vec::with_capacity(3 + user.len() + password.len());
+        request.push(1);
+        request.push(user.len() as u8);
+        request.extend_from_slice(user.as_bytes());
+        request.push(password.len() as u8);
+        request.extend_from_slice(password.as_bytes());
+        self.channel.send_all(&request)?;
+
+        let mut reply = [0u8; 2];
+        self.channel.read_exact(&mut reply)?;
+        if reply[1] != 0 {
+            return Err(ChannelError::UnableToConnect);
+        }
+        Ok(())
+    }
+
+    fn connect(&mut self, host: &str, port: u16) -> Result<(), ChannelError> {
+        if host.is_empty() || host.len() > 255 {
+            return Err(ChannelError::InvalidHostName);
+        }
+
+        let greeting: &[u8] = if self.credentials.is_some() {
+            &[VERSION, 2, NO_AUTHENTICATION, USERNAME_PASSWORD]
+        } else {
+            &[VERSION, 1, NO_AUTHENTICATION]
+        };
+        self.channel.send_all(greeting)?;
+        let mut reply = [0u8; 2];
+        self.channel.read_exact(&mut reply)?;
+        if reply[0] != VERSION {
+            return Err(ChannelError::SomethingWentWrong);
+        }
+        match (reply[1], self.credentials) {
+            (NO_AUTHENTICATION, _) => {}
+            (USERNAME_PASSWORD, Some((user, password))) => self.authenticate(user, password)?,
+            // 0xff when no method is acceptable, or one we didn't offer.
+            _ => return Err(ChannelError::UnableToConnect),
+        }
+
+        let mut request = Vec::with_capacity(7 + host.len());
+        request.extend_from_slice(&[VERSION, CONNECT, 0, ADDRESS_DOMAIN, host.len() as u8]);
+        request.extend_from_slice(host.as_bytes());
+        request.extend_from_slice(&port.to_be_bytes());
+        self.channel.send_all(&request)?;
+
+        // The reply ends with the address and port bound by the proxy, that we don't need.
+        let mut reply = [0u8; 5];
+        self.channel.read_exact(&mut reply)?;
+        if reply[0] != VERSION {
+            return Err(ChannelError::SomethingWentWrong);
+        }
+        if reply[1] != 0 {
+            return Err(ChannelError::UnableToConnect);
+        }
+        let address_len = match reply[3] {
+            ADDRESS_IPV4 => 3,
+            ADDRESS_DOMAIN => reply[4] as usize,
+            ADDRESS_IPV6 => 15,
+            _ => return Err(ChannelError::SomethingWentWrong),
+        };
+        let mut bound = [0u8; 257];
+        self.channel.read_exact(&mut bound[..address_len + 2])?;
+        Ok(())
+    }
+}
+
+impl<'p, T: Channel> Channel for Socks5Channel<'p, T> {
+    // Connects to the proxy, and asks it to connect to host:port.
+    fn open(&mut self, host: &str, port: u16, tls: bool) -> Result<(), ChannelError> {
+        let (proxy_host, proxy_port) = (self.proxy_host, self.proxy_port);
+        self.channel.open(proxy_host, proxy_port, false)?;
+        self.connect(host, port)?;
+        if tls {
+            self.channel.start_tls(host)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+        self.channel.send(data, len)
+    }
+
+    fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+        self.channel.recv(data, max_len)
+    }
+
+    fn start_tls(&mut self, host: &str) -> Result<(), ChannelError> {
+        self.channel.start_tls(host)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use collections::{String, VecDeque};
+    use core::str;
+    use {Client, HttpHeader};
+
+    // A SOCKS5 proxy stand-in, answering the client messages as they are sent. Once connected,
+    // it plays a server answering every request with the same response.
+    struct Socks5Proxy {
+        credentials: Option<(&'static str, &'static str)>,
+        // The reply code to CONNECT requests.
+        connect_reply: u8,
+        response: &'static [u8],
+        opened: Option<(String, u16)>,
+        state: u8,
+        input: Vec<u8>,
+        output: VecDeque<u8>,
+        target: Option<(String, u16)>,
+        request: Vec<u8>,
+    }
+
+    impl Socks5Proxy {
+        fn new(credentials: Option<(&'static str, &'static str)>, connect_reply: u8) -> Self {
+            Socks5Proxy {
+                credentials: credentials,
+                connect_reply: connect_reply,
+                response: b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+                opened: None,
+                state: 0,
+                input: Vec::new(),
+                output: VecDeque::new(),
+                target: None,
+                request: Vec::new(),
+            }
+        }
+
+        // Handles a complete message at the start of the input, returning its length.
+        fn handle(&mut self) -> Option<usize> {
+            let input = &self.input;
+            match self.state {
+                // Method selection.
+                0 => {
+                    let count = *input.get(1)? as usize;
+                    let methods = input.get(2..2 + count)?;
+                    assert_eq!(input[0], 5);
+                    let method = match self.credentials {
+                        Some(_) if methods.contains(&2) => 2,
+                        Some(_) => 0xff,
+                        None if methods.contains(&0) => 0,
+                        None => 0xff,
+                    };
+                    self.output.extend(&[5, method]);
+                    self.state = if method == 2 { 1 } else { 2 };
+                    Some(2 + count)
+                }
+                // Username and password.
+                1 => {
+                    let user_len = *input.get(1)? as usize;
+                    let password_len = *input.get(2 + user_len)? as usize;
+                    let user = str::from_utf8(input.get(2..2 + user_len)?).unwrap();
+                    let start = 3 + user_len;
+                    let password = input.get(start..start + password_len)?;
+                    let password = str::from_utf8(password).unwrap();
+                    let ok = self.credentials == Some((user, password));
+                    self.output.extend(&[1, if ok { 0 } else { 1 }]);
+                    self.state = 2;
+                    Some(3 + user_len + password_len)
+                }
+                // The CONNECT request, answered with a domain name as the bound address.
+                2 => {
+                    assert_eq!(input.get(..4)?, &[5, 1, 0, 3]);
+                    let host_len = *input.get(4)? as usize;
+                    let port = input.get(5 + host_len..7 + host_len)?;
+                    let host = String::from(str::from_utf8(&input[5..5 + host_len]).unwrap());
+                    self.target = Some((host, (port[0] as u16) << 8 | port[1] as u16));
+                    self.output.extend(&[5, self.connect_reply, 0, 3, 5]);
+                    self.output.extend(b"proxy");
+                    self.output.extend(&[0x1f, 0x90]);
+                    self.state = 3;
+                    Some(7 + host_len)
+                }
+                _ => {
+                    let len = input.len();
+                    self.request.extend_from_slice(input);
+                    if self.request.ends_with(b"\r\n\r\n") {
+                        self.output.extend(self.response);
+                    }
+                    Some(len)
+                }
+            }
+        }
+    }
+
+    impl Channel for Socks5Proxy {
+        fn open(&mut self, host: &str, port: u16, tls: bool) -> Result<(), ChannelError> {
+            assert!(!tls);
+            self.opened = Some((String::from(host), port));
+            Ok(())
+        }
+
+        fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+            self.input.extend_from_slice(&data[..len]);
+            while !self.input.is_empty() {
+                match self.handle() {
+                    Some(size) => {
+                        self.input.drain(..size);
+                    }
+                    None => break,
+                }
+            }
+            Ok(len)
+        }
+
+        fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+            if max_len != 0 && self.output.is_empty() {
+                return Err(ChannelError::EndOfStream);
+            }
+            let mut size = 0;
+            while size < max_len {
+                match self.output.pop_front() {
+                    Some(value) => data[size] = value,
+                    None => break,
+                }
+                size += 1;
+            }
+            Ok(size)
+        }
+    }
+
+    #[test]
+    fn test_socks5_client() {
+        let mut channel = Socks5Channel::new(Socks5Proxy::new(None, 0), "localhost", 1080);
+        {
+            let mut client = Client::new(&mut channel);
+            let response = client.get("http://example.com:8080/status")
+                .open()
+                .unwrap()
+                .response(|name| name == HttpHeader::ContentLength)
+                .unwrap();
+            assert_eq!(response.status_code, 200);
+            let mut buffer = [0u8; 8];
+            assert_eq!(response.body.read_string_to_end(&mut buffer).unwrap(), "ok");
+        }
+        let proxy = channel.into_inner();
+        assert_eq!(proxy.opened, Some((String::from("localhost"), 1080)));
+        assert_eq!(proxy.target, Some((String::from("example.com"), 8080)));
+        assert_eq!(str::from_utf8(&proxy.request).unwrap(),
+                   "GET /status HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    }
+
+    #[test]
+    fn test_socks5_authentication() {
+        let proxy = Socks5Proxy::new(Some(("user", "secret")), 0);
+        let mut channel = Socks5Channel::new(proxy, "localhost", 1080)
+            .with_credentials("user", "secret");
+        channel.open("example.com", 80, false).unwrap();
+        assert_eq!(channel.into_inner().target, Some((String::from("example.com"), 80)));
+
+        let proxy = Socks5Proxy::new(Some(("user", "secret")), 0);
+        let mut channel = Socks5Channel::new(proxy, "localhost", 1080)
+            .with_credentials("user", "wrong");
+        assert_eq!(channel.open("example.com", 80, false),
+                   Err(ChannelError::UnableToConnect));
+
+        // The proxy requires credentials that we don't have.
+        let proxy = Socks5Proxy::new(Some(("user", "secret")), 0);
+        let mut channel = Socks5Channel::new(proxy, "localhost", 1080);
+        assert_eq!(channel.open("example.com", 80, false),
+                   Err(ChannelError::UnableToConnect));
+    }
+
+    #[test]
+    fn test_socks5_errors() {
+        // Host unreachable.
+        let mut channel = Socks5Channel::new(Socks5Proxy::new(None, 4), "localhost", 1080);
+        assert_eq!(channel.open("example.com", 80, false),
+                   Err(ChannelError::UnableToConnect));
+
+        let mut channel = Socks5Channel::new(Socks5Proxy::new(None, 0), "localhost", 1080);
+        assert_eq!(channel.open("", 80, false), Err(ChannelError::InvalidHostName));
+
+        // The proxy stand-in can't do TLS.
+        let mut channel = Socks5Channel::new(Socks5Proxy::new(None, 0), "localhost", 1080);
+        assert_eq!(channel.open("example.com", 443, true),
+                   Err(ChannelError::TlsUnsupported));
+    }
+}
@@ -0,0 +1,476 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Server-Sent Events: parsing of `text/event-stream` bodies, and a reconnecting
+// event source on top of `Client`, as specified in the HTML living standard.
+
+use collections::{String, Vec};
+use core::str::FromStr;
+
+use chunked::ChunkedReader;
+use traits::{Channel, ChannelError, Sleep};
+use {Client, HttpError, HttpHeader};
+
+// Maximum length of a line in the stream.
+const MAX_LINE_LENGTH: usize = 4096;
+
+static BOM: &'static [u8] = b"\xef\xbb\xbf";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    // The last event id seen in the stream, possibly set by an earlier event.
+    pub id: String,
+    pub event: String,
+    // Data lines are joined with line feeds.
+    pub data: String,
+    // The reconnection time sent with this event, in milliseconds.
+    pub retry: Option<u32>,
+}
+
+pub struct EventReader<'a, T: 'a> {
+    channel: &'a mut T,
+    last_event_id: String,
+    retry: Option<u32>,
+    // The previous line ended with a CR, so a LF right after it is part of the line end.
+    after_cr: bool,
+    started: bool,
+}
+
+impl<'a, T: Channel> EventReader<'a, T> {
+    pub fn new(channel: &'a mut T) -> Self {
+        EventReader {
+            channel: channel,
+            last_event_id: String::new(),
+            retry: None,
+            after_cr: false,
+            started: false,
+        }
+    }
+
+    pub fn last_event_id(&self) -> &str {
+        &self.last_event_id
+    }
+
+    // The last reconnection time sent by the server, in milliseconds.
+    pub fn retry(&self) -> Option<u32> {
+        self.retry
+    }
+
+    // Reads a line ended by CR, LF or CRLF. Returns false at the end of the stream, the
+    // pending partial line being dropped.
+    fn read_line(&mut self, line: &mut Vec<u8>) -> Result<bool, ChannelError> {
+        line.clear();
+        let mut value = [0u8];
+        loop {
+            match self.channel.recv(&mut value, 1) {
+                Ok(0) => continue,
+                Ok(_) => {}
+                Err(ChannelError::EndOfStream) => return Ok(false),
+                Err(err) => return Err(err),
+            }
+
+            let after_cr = self.after_cr;
+            self.after_cr = false;
+            match value[0] {
+                b'\n' if after_cr => {}
+                b'\n' => return Ok(true),
+                b'\r' => {
+                    self.after_cr = true;
+                    return Ok(true);
+                }
+                value => {
+                    if line.len() == MAX_LINE_LENGTH {
+                        return Err(ChannelError::BufferFull);
+                    }
+                    line.push(value);
+                }
+            }
+        }
+    }
+
+    // Returns the next event, or None at the end of the stream.
+    pub fn next_event(&mut self) -> Result<Option<Event>, HttpError> {
+        let mut line = Vec::new();
+        let mut event = String::new();
+        let mut data = String::new();
+        let mut retry = None;
+
+        while self.read_line(&mut line)? {
+            if !self.started {
+                self.started = true;
+                if line.starts_with(BOM) {
+                    line.drain(..BOM.len());
+                }
+            }
+
+            // An empty line dispatches the event, if it has some data.
+            if line.is_empty() {
+                if data.is_empty() {
+                    event.clear();
+                    retry = None;
+                    continue;
+                }
+                data.pop();
+                if event.is_empty() {
+                    event.push_str("message");
+                }
+                return Ok(Some(Event {
+                    id: self.last_event_id.clone(),
+                    event: event,
+                    data: data,
+                    retry: retry,
+                }));
+            }
+
+            let line = String::from_utf8_lossy(&line);
+            // Comments start with a colon.
+            if line.starts_with(':') {
+                continue;
+            }
+            let (field, value) = match line.find(':') {
+                Some(pos) => {
+                    let value = &line[pos + 1..];
+                    (&line[..pos], value.strip_prefix(' ').unwrap_or(value))
+                }
+                None => (&line[..], ""),
+            };
+
+            match field {
+                "event" => event = String::from(value),
+                "data" => {
+                    data.push_str(value);
+                    data.push('\n');
+                }
+                "id" if !value.contains('\0') => self.last_event_id = String::from(value),
+                "retry" if value.bytes().all(|value| value.is_ascii_digit()) => {
+                    if let Ok(value) = u32::from_str(value) {
+                        self.retry = Some(value);
+                        retry = Some(value);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+// Keeps a stream of events going, reconnecting when the connection drops.
+pub struct EventSource<'u> {
+    url: &'u str,
+    last_event_id: String,
+    // Reconnection delay in milliseconds.
+    retry: u32,
+    max_reconnects: Option<u32>,
+    // An event was received since the last connection.
+    received: bool,
+}
+
+impl<'u> EventSource<'u> {
+    pub fn new(url: &'u str) -> Self {
+        EventSource {
+            url: url,
+            last_event_id: String::new(),
+            retry: 3000,
+            max_reconnects: None,
+            received: false,
+        }
+    }
+
+    // The delay before reconnecting, until the server sends its own.
+    pub fn set_retry(&mut self, ms: u32) {
+        self.retry = ms;
+    }
+
+    // Gives up after this many failed connection attempts in a row, connections closed before
+    // any event counting as failed. The default is to retry forever.
+    pub fn set_max_reconnects(&mut self, max: Option<u32>) {
+        self.max_reconnects = max;
+    }
+
+    // The id sent as Last-Event-ID when reconnecting.
+    pub fn last_event_id(&self) -> &str {
+        &self.last_event_id
+    }
+
+    // Calls `handler` for every event until it returns false, or the server answers with
+    // a 204 status. Responses that are not event streams end the loop with an error.
+    pub fn run<T, S, F>(&mut self,
+                        client: &mut Client<'u, T>,
+                        sleep: &mut S,
+                        mut handler: F)
+                        -> Result<(), HttpError>
+        where T: Channel,
+              S: Sleep,
+              F: FnMut(&Event) -> bool
+    {
+        let mut failures = 0;
+        loop {
+            self.received = false;
+            let err = match self.connect(client, &mut handler) {
+                Ok(true) => return Ok(()),
+                Ok(false) => HttpError::ChannelError(ChannelError::EndOfStream),
+                Err(err @ HttpError::UnexpectedStatus(_)) |
+                Err(err @ HttpError::UnexpectedContentType) => return Err(err),
+                Err(err) => err,
+            };
+            if self.received {
+                failures = 0;
+            } else {
+                failures += 1;
+                if self.max_reconnects.map(|max| failures > max) == Some(true) {
+                    return Err(err);
+                }
+            }
+            sleep.sleep_ms(self.retry);
+        }
+    }
+
+    // Reads events from a new connection. Returns true if we are done, and false if the
+    // connection dropped.
+    fn connect<T, F>(&mut self, client: &mut Client<'u, T>, handler: &mut F) -> Result<bool, HttpError>
+        where T: Channel,
+              F: FnMut(&Event) -> bool
+    {
+        let request = client.get(self.url)
+            .open()?
+            .headers(&[(HttpHeader::Other(String::from("Accept:")), "text/event-stream"),
+                       (HttpHeader::CacheControl, "no-cache")])?;
+        if !self.last_event_id.is_empty() {
+            request.header(HttpHeader::Other(String::from("Last-Event-ID:")),
+                        &self.last_event_id)?;
+        }
+        let response = request.response(|header| {
+                header == HttpHeader::ContentType || header == HttpHeader::TransferEncoding
+            })?;
+
+        if response.status_code == 204 {
+            return Ok(true);
+        }
+        if response.status_code != 200 {
+            return Err(HttpError::UnexpectedStatus(response.status_code));
+        }
+        let content_type = response.headers
+            .iter()
+            .find(|header| header.0 == HttpHeader::ContentType)
+            .map_or("", |header| header.1.split(';').next().unwrap().trim());
+        if !content_type.eq_ignore_ascii_case("text/event-stream") {
+            return Err(HttpError::UnexpectedContentType);
+        }
+
+        let chunked = response.headers
+            .iter()
+            .any(|header| header.0 == HttpHeader::TransferEncoding &&
+                          header.1.trim().eq_ignore_ascii_case("chunked"));
+        if chunked {
+            self.read_events(&mut ChunkedReader::new(response.body), handler)
+        } else {
+            self.read_events(response.body, handler)
+        }
+    }
+
+    // Passes the events of a stream to the handler. Returns true if we are done, and false if
+    // the stream ended.
+    fn read_events<C, F>(&mut self, channel: &mut C, handler: &mut F) -> Result<bool, HttpError>
+        where C: Channel,
+              F: FnMut(&Event) -> bool
+    {
+        let mut reader = EventReader::new(channel);
+        reader.last_event_id = self.last_event_id.clone();
+        loop {
+            let res = reader.next_event();
+            if reader.last_event_id != self.last_event_id {
+                self.last_event_id = reader.last_event_id.clone();
+            }
+            if let Some(retry) = reader.retry {
+                self.retry = retry;
+            }
+
+            match res {
+                Ok(Some(event)) => {
+                    self.received = true;
+                    if !handler(&event) {
+                        return Ok(true);
+                    }
+                }
+                // Errors while reading are handled as a dropped connection.
+                Ok(None) | Err(_) => return Ok(false),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::str;
+    use traits::StringChannel;
+
+    // Answers each connection with the next response.
+    struct ScriptChannel {
+        responses: Vec<&'static str>,
+        current: StringChannel<'static>,
+        requests: Vec<String>,
+    }
+
+    impl ScriptChannel {
+        fn new(responses: &[&'static str]) -> Self {
+            ScriptChannel {
+                responses: responses.to_vec(),
+                current: StringChannel::new(""),
+                requests: Vec::new(),
+            }
+        }
+    }
+
+    impl Channel for ScriptChannel {
+        fn open(&mut self, _: &str, _: u16, _: bool) -> Result<(), ChannelError> {
+            if self.responses.is_empty() {
+                return Err(ChannelError::UnableToConnect);
+            }
+            self.current = StringChannel::new(self.responses.remove(0));
+            self.requests.push(String::new());
+            Ok(())
+        }
+
+        fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+            self.requests.last_mut().unwrap().push_str(str::from_utf8(&data[..len]).unwrap());
+            Ok(len)
+        }
+
+        fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+            self.current.recv(data, max_len)
+        }
+    }
+
+    struct Sleeps(Vec<u32>);
+
+    impl Sleep for Sleeps {
+        fn sleep_ms(&mut self, ms: u32) {
+            self.0.push(ms);
+        }
+    }
+
+    fn event(id: &str, event: &str, data: &str, retry: Option<u32>) -> Event {
+        Event {
+            id: String::from(id),
+            event: String::from(event),
+            data: String::from(data),
+            retry: retry,
+        }
+    }
+
+    #[test]
+    fn test_event_reader() {
+        let mut channel = StringChannel::new("\u{feff}: comment\n\ndata: first\r\ndata:second\r\rid: \
+                                              7\nevent: update\nretry: 1500\ndata\n\nid: \
+                                              8\n\nretry: soon\ndata:  spaced\r\n\r\ndata: \
+                                              incomplete\n");
+        let mut reader = EventReader::new(&mut channel);
+        assert_eq!(reader.next_event().unwrap(),
+                   Some(event("", "message", "first\nsecond", None)));
+        assert_eq!(reader.next_event().unwrap(),
+                   Some(event("7", "update", "", Some(1500))));
+        // The id of an event without data is kept for the next ones.
+        assert_eq!(reader.next_event().unwrap(),
+                   Some(event("8", "message", " spaced", None)));
+        assert_eq!(reader.next_event().unwrap(), None);
+        assert_eq!(reader.last_event_id(), "8");
+        assert_eq!(reader.retry(), Some(1500));
+    }
+
+    #[test]
+    fn test_event_source() {
+        let mut channel = ScriptChannel::new(&["HTTP/1.1 200 OK\r\nContent-Type: \
+                                                text/event-stream\r\n\r\nretry: 500\nid: \
+                                                1\ndata: a\n\n",
+                                               "HTTP/1.1 200 OK\r\nContent-Type: \
+                                                text/event-stream; charset=utf-8\r\n\r\nid: \
+                                                2\ndata: b\n\ndata: stop\n\ndata: never\n\n"]);
+        let mut sleeps = Sleeps(Vec::new());
+        let mut events = Vec::new();
+        {
+            let mut client = Client::new(&mut channel);
+            let mut source = EventSource::new("http://localhost/events");
+            source.run(&mut client, &mut sleeps, |event| {
+                    events.push(event.data.clone());
+                    event.data != "stop"
+                })
+                .unwrap();
+            assert_eq!(source.last_event_id(), "2");
+        }
+
+        assert_eq!(events, ["a", "b", "stop"]);
+        assert_eq!(sleeps.0, [500]);
+        assert_eq!(channel.requests[0],
+                   "GET /events HTTP/1.1\r\nHost: localhost\r\nAccept: \
+                    text/event-stream\r\nCache-Control: no-cache\r\n\r\n");
+        assert!(channel.requests[1].ends_with("Last-Event-ID: 1\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_event_source_chunked() {
+        let mut channel = ScriptChannel::new(&["HTTP/1.1 200 OK\r\nContent-Type: \
+                                                text/event-stream\r\nTransfer-Encoding: \
+                                                chunked\r\n\r\n9\r\ndata: a\n\n\r\n17\r\nid: \
+                                                5\ndata: b\ndata: c\n\n\r\n0\r\n\r\n"]);
+        let mut sleeps = Sleeps(Vec::new());
+        let mut events = Vec::new();
+        let mut source = EventSource::new("http://localhost/events");
+        source.set_max_reconnects(Some(0));
+        let res = source.run(&mut Client::new(&mut channel), &mut sleeps, |event| {
+            events.push(event.data.clone());
+            true
+        });
+        assert_eq!(res, Err(HttpError::ChannelError(ChannelError::UnableToConnect)));
+        assert_eq!(events, ["a", "b\nc"]);
+        assert_eq!(source.last_event_id(), "5");
+    }
+
+    #[test]
+    fn test_event_source_end() {
+        let mut sleeps = Sleeps(Vec::new());
+
+        let mut channel = ScriptChannel::new(&["HTTP/1.1 204 No Content\r\n\r\n"]);
+        let mut source = EventSource::new("http://localhost/events");
+        source.run(&mut Client::new(&mut channel), &mut sleeps, |_| true).unwrap();
+
+        let mut channel = ScriptChannel::new(&["HTTP/1.1 200 OK\r\nContent-Type: \
+                                                text/html\r\n\r\n"]);
+        assert_eq!(source.run(&mut Client::new(&mut channel), &mut sleeps, |_| true)
+                       .err()
+                       .unwrap(),
+                   HttpError::UnexpectedContentType);
+
+        let mut channel = ScriptChannel::new(&["HTTP/1.1 503 Unavailable\r\n\r\n"]);
+        assert_eq!(source.run(&mut Client::new(&mut channel), &mut sleeps, |_| true)
+                       .err()
+                       .unwrap(),
+                   HttpError::UnexpectedStatus(503));
+        assert!(sleeps.0.is_empty());
+
+        // Connections closed without events are failures.
+        let mut channel = ScriptChannel::new(&["HTTP/1.1 200 OK\r\nContent-Type: \
+                                                text/event-stream\r\n\r\n",
+                                               "HTTP/1.1 200 OK\r\nContent-Type: \
+                                                text/event-stream\r\n\r\n"]);
+        source.set_max_reconnects(Some(1));
+        assert_eq!(source.run(&mut Client::new(&mut channel), &mut sleeps, |_| true)
+                       .err()
+                       .unwrap(),
+                   HttpError::ChannelError(ChannelError::EndOfStream));
+        assert_eq!(sleeps.0, [3000]);
+        sleeps.0.clear();
+
+        // Nobody answers anymore.
+        let mut channel = ScriptChannel::new(&[]);
+        source.set_max_reconnects(Some(2));
+        assert_eq!(source.run(&mut Client::new(&mut channel), &mut sleeps, |_| true)
+                       .err()
+                       .unwrap(),
+                   HttpError::ChannelError(ChannelError::UnableToConnect));
+        assert_eq!(sleeps.0, [3000, 3000]);
+    }
+}
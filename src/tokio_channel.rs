@@ -0,0 +1,254 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// An `AsyncChannel` implementation over tokio's TcpStream.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use std::boxed::Box;
+use std::io;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::time::{self, error::Elapsed};
+
+use async_client::AsyncChannel;
+use traits::ChannelError;
+
+type Connecting = Pin<Box<dyn Future<Output = Result<io::Result<TcpStream>, Elapsed>> + Send>>;
+
+pub struct TokioChannel {
+    stream: Option<TcpStream>,
+    connecting: Option<Connecting>,
+    connect_timeout: Duration,
+}
+
+impl TokioChannel {
+    pub fn new() -> Self {
+        TokioChannel {
+            stream: None,
+            connecting: None,
+            connect_timeout: Duration::from_secs(30),
+        }
+    }
+
+    pub fn with_connect_timeout(connect_timeout: Duration) -> Self {
+        TokioChannel {
+            stream: None,
+            connecting: None,
+            connect_timeout: connect_timeout,
+        }
+    }
+}
+
+impl Default for TokioChannel {
+    fn default() -> Self {
+        TokioChannel::new()
+    }
+}
+
+fn channel_error(err: io::Error) -> ChannelError {
+    match err.kind() {
+        io::ErrorKind::ConnectionRefused => ChannelError::UnableToConnect,
+        io::ErrorKind::NotConnected => ChannelError::NotConnected,
+        io::ErrorKind::TimedOut => ChannelError::Timeout,
+        io::ErrorKind::UnexpectedEof |
+        io::ErrorKind::ConnectionReset |
+        io::ErrorKind::ConnectionAborted |
+        io::ErrorKind::BrokenPipe => ChannelError::EndOfStream,
+        _ => ChannelError::SomethingWentWrong,
+    }
+}
+
+impl AsyncChannel for TokioChannel {
+    fn poll_open(&mut self,
+                 cx: &mut Context,
+                 host: &str,
+                 port: u16,
+                 tls: bool)
+                 -> Poll<Result<(), ChannelError>> {
+        if tls {
+            return Poll::Ready(Err(ChannelError::TlsUnsupported));
+        }
+
+        if self.connecting.is_none() {
+            // Reopening drops any previous connection.
+            self.stream = None;
+            let connect = TcpStream::connect(format!("{}:{}", host, port));
+            self.connecting = Some(Box::pin(time::timeout(self.connect_timeout, connect)));
+        }
+
+        let result = match self.connecting.as_mut().unwrap().as_mut().poll(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(result) => result,
+        };
+        self.connecting = None;
+
+        match result {
+            Ok(Ok(stream)) => {
+                // Requests are written in small pieces, don't let Nagle delay them.
+                let _ = stream.set_nodelay(true);
+                self.stream = Some(stream);
+                Poll::Ready(Ok(()))
+            }
+            Ok(Err(ref err)) if err.kind() == io::ErrorKind::TimedOut => {
+                Poll::Ready(Err(ChannelError::Timeout))
+            }
+            Ok(Err(_)) => Poll::Ready(Err(ChannelError::UnableToConnect)),
+            Err(_) => Poll::Ready(Err(ChannelError::Timeout)),
+        }
+    }
+
+    fn poll_send(&mut self, cx: &mut Context, data: &[u8]) -> Poll<Result<usize, ChannelError>> {
+        let stream = match self.stream {
+            Some(ref mut stream) => stream,
+            None => return Poll::Ready(Err(ChannelError::NotConnected)),
+        };
+
+        match Pin::new(stream).poll_write(cx, data) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(result) => Poll::Ready(result.map_err(channel_error)),
+        }
+    }
+
+    fn poll_recv(&mut self,
+                 cx: &mut Context,
+                 data: &mut [u8])
+                 -> Poll<Result<usize, ChannelError>> {
+        let stream = match self.stream {
+            Some(ref mut stream) => stream,
+            None => return Poll::Ready(Err(ChannelError::NotConnected)),
+        };
+
+        if data.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let mut buf = ReadBuf::new(data);
+        match Pin::new(stream).poll_read(cx, &mut buf) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(err)) => Poll::Ready(Err(channel_error(err))),
+            // Nothing was read in a non empty buffer: the peer closed the connection.
+            Poll::Ready(Ok(())) if buf.filled().is_empty() => {
+                Poll::Ready(Err(ChannelError::EndOfStream))
+            }
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(buf.filled().len())),
+        }
+    }
+
+    // Flushes and shuts down the write side, then releases the connection.
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<Result<(), ChannelError>> {
+        self.connecting = None;
+        let result = match self.stream {
+            Some(ref mut stream) => {
+                match Pin::new(stream).poll_shutdown(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(result) => result,
+                }
+            }
+            None => return Poll::Ready(Ok(())),
+        };
+        self.stream = None;
+
+        match result {
+            // The peer may have closed the connection first.
+            Err(ref err) if err.kind() == io::ErrorKind::NotConnected => Poll::Ready(Ok(())),
+            Err(err) => Poll::Ready(Err(channel_error(err))),
+            Ok(()) => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_client::AsyncClient;
+    use std::string::String;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::vec::Vec;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::runtime::{Builder, Runtime};
+    use HttpHeader;
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread().enable_all().build().unwrap()
+    }
+
+    // Runs a tokio listener on its own thread, answering a single request with `response`.
+    // Returns the port, and a handle resolving to the received request once the client
+    // closed its side of the connection.
+    fn serve(response: &'static str) -> (u16, thread::JoinHandle<String>) {
+        let (port_sender, port_receiver) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let rt = runtime();
+            let listener = rt.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+            port_sender.send(listener.local_addr().unwrap().port()).unwrap();
+
+            let (mut stream, _) = rt.block_on(listener.accept()).unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 256];
+            while !request.ends_with(b"\r\n\r\n") {
+                let size = rt.block_on(stream.read(&mut buffer)).unwrap();
+                assert!(size > 0);
+                request.extend_from_slice(&buffer[..size]);
+            }
+            rt.block_on(stream.write_all(response.as_bytes())).unwrap();
+
+            // Wait for the client to shut down the connection.
+            assert_eq!(rt.block_on(stream.read(&mut buffer)).unwrap(), 0);
+            String::from_utf8(request).unwrap()
+        });
+        (port_receiver.recv().unwrap(), handle)
+    }
+
+    #[test]
+    fn test_tokio_get() {
+        let (port, server) = serve("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello");
+        let url = format!("http://127.0.0.1:{}/hello", port);
+
+        let rt = runtime();
+        let mut client = AsyncClient::new(TokioChannel::new());
+        let client = rt.block_on(client.get(&url).open()).unwrap();
+        let client = rt.block_on(client.send(&[])).unwrap();
+        let response = rt.block_on(client.response(|_| true)).unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.headers[0],
+                   (HttpHeader::ContentLength, String::from("5")));
+
+        let mut buffer = [0u8; 5];
+        let mut read = 0;
+        while read < buffer.len() {
+            read += rt.block_on(response.body.recv(&mut buffer[read..])).unwrap();
+        }
+        assert_eq!(&buffer, b"Hello");
+
+        rt.block_on(response.body.close()).unwrap();
+        assert_eq!(server.join().unwrap(),
+                   "GET /hello HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n");
+
+        // The channel is released after closing.
+        let mut buffer = [0u8; 1];
+        assert_eq!(rt.block_on(response.body.recv(&mut buffer)).err().unwrap(),
+                   ChannelError::NotConnected);
+    }
+
+    #[test]
+    fn test_tokio_connection_refused() {
+        let rt = runtime();
+        // Grab a free port, and close the listener right away.
+        let port = {
+            let listener = rt.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+            listener.local_addr().unwrap().port()
+        };
+
+        let mut channel = TokioChannel::with_connect_timeout(Duration::from_secs(5));
+        assert_eq!(rt.block_on(channel.open("127.0.0.1", port, false)).err().unwrap(),
+                   ChannelError::UnableToConnect);
+        assert_eq!(rt.block_on(channel.open("127.0.0.1", port, true)).err().unwrap(),
+                   ChannelError::TlsUnsupported);
+    }
+}
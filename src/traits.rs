@@ -2,6 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this file,
 // You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use collections::Vec;
 use core::str;
 
 #[derive(Debug, PartialEq)]
@@ -14,6 +15,8 @@ pub enum ChannelError {
     InvalidDelimiterChar,
     InvalidString,
     TlsUnsupported,
+    NotConnected,
+    Timeout,
 }
 
 pub trait Channel {
@@ -28,10 +31,43 @@ pub trait Channel {
         self.send(data.as_bytes(), data.len())
     }
 
+    // Sends all the data, for channels that may accept only part of it at once.
+    fn send_all(&mut self, data: &[u8]) -> Result<(), ChannelError> {
+        let mut pos = 0;
+        while pos < data.len() {
+            let size = self.send(&data[pos..], data.len() - pos)?;
+            if size == 0 {
+                return Err(ChannelError::EndOfStream);
+            }
+            pos += size;
+        }
+        Ok(())
+    }
+
+    // Starts TLS with `host` on the open connection, eg. through a proxy tunnel. Channels
+    // that can't upgrade connections keep this default.
+    fn start_tls(&mut self, _host: &str) -> Result<(), ChannelError> {
+        Err(ChannelError::TlsUnsupported)
+    }
+
     // Tries to receive at most `max_len` bytes.
     // Returns the number of bytes successfully received, or an error.
     fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError>;
 
+    // Fills the buffer, failing if we reach eof before.
+    fn read_exact(&mut self, data: &mut [u8]) -> Result<(), ChannelError> {
+        let mut pos = 0;
+        while pos < data.len() {
+            let len = data.len() - pos;
+            let size = self.recv(&mut data[pos..], len)?;
+            if size == 0 {
+                return Err(ChannelError::EndOfStream);
+            }
+            pos += size;
+        }
+        Ok(())
+    }
+
     // Reads data in the buffer until eof of the end of the buffer.
     fn read_to_end(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
         let mut i = 0;
@@ -111,6 +147,42 @@ pub trait Channel {
     }
 }
 
+// Lets a channel be used through a mutable reference, keeping ownership of it.
+impl<'a, T: Channel + ?Sized> Channel for &'a mut T {
+    fn open(&mut self, host: &str, port: u16, tls: bool) -> Result<(), ChannelError> {
+        (**self).open(host, port, tls)
+    }
+
+    fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+        (**self).send(data, len)
+    }
+
+    fn start_tls(&mut self, host: &str) -> Result<(), ChannelError> {
+        (**self).start_tls(host)
+    }
+
+    fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+        (**self).recv(data, max_len)
+    }
+}
+
+// A source of random bytes, for nonces and masking keys. The quality needed depends on the
+// use: WebSocket masks only need to be unpredictable to the network.
+pub trait Entropy {
+    fn fill(&mut self, data: &mut [u8]);
+}
+
+// Blocks the caller for a while, eg. between reconnection attempts.
+pub trait Sleep {
+    fn sleep_ms(&mut self, ms: u32);
+}
+
+// The wall clock time, eg. from an RTC or SNTP.
+pub trait Clock {
+    // Seconds since the Unix epoch.
+    fn now(&mut self) -> u64;
+}
+
 /// A simple channel implementation using a string as the source.
 #[derive(Clone)]
 pub struct StringChannel<'a> {
@@ -132,8 +204,9 @@ impl<'a> Channel for StringChannel<'a> {
         Ok(())
     }
 
-    fn send(&mut self, _: &[u8], _: usize) -> Result<usize, ChannelError> {
-        Ok(0)
+    // Sent data is discarded.
+    fn send(&mut self, _: &[u8], len: usize) -> Result<usize, ChannelError> {
+        Ok(len)
     }
 
     fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
@@ -160,10 +233,99 @@ impl<'a> Channel for StringChannel<'a> {
     }
 }
 
+/// A channel reading from a byte slice, and keeping the data sent in memory.
+#[derive(Clone)]
+pub struct MemoryChannel<'a> {
+    pos: usize,
+    data: &'a [u8],
+    sent: Vec<u8>,
+}
+
+impl<'a> MemoryChannel<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        MemoryChannel {
+            pos: 0,
+            data: data,
+            sent: Vec::new(),
+        }
+    }
+
+    pub fn sent(&self) -> &[u8] {
+        &self.sent
+    }
+}
+
+impl<'a> Channel for MemoryChannel<'a> {
+    fn open(&mut self, _: &str, _: u16, _tls: bool) -> Result<(), ChannelError> {
+        Ok(())
+    }
+
+    fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+        self.sent.extend_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+        if max_len != 0 && self.pos >= self.data.len() {
+            return Err(ChannelError::EndOfStream);
+        }
+
+        // Return what we have, up to max_len.
+        let mut i = 0;
+        while i < max_len && self.pos < self.data.len() {
+            data[i] = self.data[self.pos];
+            i += 1;
+            self.pos += 1;
+        }
+        Ok(i)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    // Moves at most 3 bytes per call.
+    struct Trickle<'a> {
+        input: &'a [u8],
+        sent: Vec<u8>,
+    }
+
+    impl<'a> Channel for Trickle<'a> {
+        fn open(&mut self, _: &str, _: u16, _: bool) -> Result<(), ChannelError> {
+            Ok(())
+        }
+
+        fn send(&mut self, data: &[u8], len: usize) -> Result<usize, ChannelError> {
+            let len = if len > 3 { 3 } else { len };
+            self.sent.extend_from_slice(&data[..len]);
+            Ok(len)
+        }
+
+        fn recv(&mut self, data: &mut [u8], max_len: usize) -> Result<usize, ChannelError> {
+            let len = [max_len, self.input.len(), 3].iter().cloned().min().unwrap();
+            data[..len].copy_from_slice(&self.input[..len]);
+            self.input = &self.input[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_send_all_read_exact() {
+        let mut channel = Trickle {
+            input: b"0123456789",
+            sent: Vec::new(),
+        };
+        channel.send_all(b"Hello, World").unwrap();
+        assert_eq!(channel.sent, b"Hello, World");
+
+        let mut buffer = [0u8; 8];
+        channel.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"01234567");
+        assert_eq!(channel.read_exact(&mut buffer).err().unwrap(),
+                   ChannelError::EndOfStream);
+    }
+
     #[test]
     fn test_readline() {
         let mut buffer: [u8; 128] = [0; 128];
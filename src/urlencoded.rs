@@ -0,0 +1,175 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// application/x-www-form-urlencoded serializing and parsing, following the WHATWG URL
+// standard. The same format is used for query strings.
+
+use collections::{String, Vec};
+
+static HEX: &'static [u8] = b"0123456789ABCDEF";
+
+// Percent-encodes a name or value. Spaces become `+`.
+pub fn encode(value: &str) -> String {
+    let mut res = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b' ' => res.push('+'),
+            b'*' | b'-' | b'.' | b'_' => res.push(byte as char),
+            _ if byte.is_ascii_alphanumeric() => res.push(byte as char),
+            _ => {
+                res.push('%');
+                res.push(HEX[(byte >> 4) as usize] as char);
+                res.push(HEX[(byte & 0xf) as usize] as char);
+            }
+        }
+    }
+    res
+}
+
+fn hex_value(value: u8) -> Option<u8> {
+    match value {
+        b'0'..=b'9' => Some(value - b'0'),
+        b'a'..=b'f' => Some(value - b'a' + 10),
+        b'A'..=b'F' => Some(value - b'A' + 10),
+        _ => None,
+    }
+}
+
+// Decodes a name or value. Invalid percent sequences are kept as they are, and invalid
+// UTF-8 is replaced.
+pub fn decode(value: &str) -> String {
+    decode_bytes(value, true)
+}
+
+// Decodes percent sequences only, as in url components.
+pub fn percent_decode(value: &str) -> String {
+    decode_bytes(value, false)
+}
+
+fn decode_bytes(value: &str, plus_as_space: bool) -> String {
+    let input = value.as_bytes();
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'+' if plus_as_space => bytes.push(b' '),
+            b'%' => {
+                match (input.get(i + 1).and_then(|value| hex_value(*value)),
+                       input.get(i + 2).and_then(|value| hex_value(*value))) {
+                    (Some(high), Some(low)) => {
+                        bytes.push(high << 4 | low);
+                        i += 2;
+                    }
+                    _ => bytes.push(b'%'),
+                }
+            }
+            byte => bytes.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// Serializes name/value pairs, eg. for a form POST body.
+pub fn encode_pairs(pairs: &[(&str, &str)]) -> String {
+    let mut res = String::new();
+    for pair in pairs {
+        if !res.is_empty() {
+            res.push('&');
+        }
+        res.push_str(&encode(pair.0));
+        res.push('=');
+        res.push_str(&encode(pair.1));
+    }
+    res
+}
+
+// Parses a form body or a query string into decoded name/value pairs.
+pub fn decode_pairs(input: &str) -> Vec<(String, String)> {
+    input.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.find('=') {
+            Some(pos) => (decode(&pair[..pos]), decode(&pair[pos + 1..])),
+            None => (decode(pair), String::new()),
+        })
+        .collect()
+}
+
+// Appends query parameters to a url or path, keeping any existing query and fragment.
+pub struct QueryBuilder {
+    url: String,
+    fragment: String,
+    has_query: bool,
+}
+
+impl QueryBuilder {
+    pub fn new(url: &str) -> Self {
+        let (url, fragment) = match url.find('#') {
+            Some(pos) => (&url[..pos], &url[pos..]),
+            None => (url, ""),
+        };
+        QueryBuilder {
+            url: String::from(url),
+            fragment: String::from(fragment),
+            has_query: url.contains('?'),
+        }
+    }
+
+    pub fn param(&mut self, name: &str, value: &str) -> &mut Self {
+        if !self.has_query {
+            self.url.push('?');
+            self.has_query = true;
+        } else if !self.url.ends_with('?') && !self.url.ends_with('&') {
+            self.url.push('&');
+        }
+        self.url.push_str(&encode(name));
+        self.url.push('=');
+        self.url.push_str(&encode(value));
+        self
+    }
+
+    pub fn build(&self) -> String {
+        format!("{}{}", self.url, self.fragment)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("a b"), "a+b");
+        assert_eq!(encode("é"), "%C3%A9");
+        assert_eq!(encode("*-._~!'()"), "*-._%7E%21%27%28%29");
+        assert_eq!(encode("a+b&c=d/e?"), "a%2Bb%26c%3Dd%2Fe%3F");
+        assert_eq!(encode_pairs(&[("name", "a b"), ("x", "é")]), "name=a+b&x=%C3%A9");
+        assert_eq!(encode_pairs(&[]), "");
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("a+b%20c"), "a b c");
+        assert_eq!(decode("%c3%A9"), "é");
+        assert_eq!(decode("100%"), "100%");
+        assert_eq!(decode("%zz%4"), "%zz%4");
+        assert_eq!(decode("%ff"), "\u{fffd}");
+        assert_eq!(percent_decode("a+b%40c"), "a+b@c");
+        assert_eq!(decode_pairs("name=a+b&&x=%C3%A9&flag&=v&k=1=2"),
+                   [(String::from("name"), String::from("a b")),
+                    (String::from("x"), String::from("é")),
+                    (String::from("flag"), String::new()),
+                    (String::new(), String::from("v")),
+                    (String::from("k"), String::from("1=2"))]);
+    }
+
+    #[test]
+    fn test_query_builder() {
+        assert_eq!(QueryBuilder::new("/search").param("q", "a b").param("lang", "fr").build(),
+                   "/search?q=a+b&lang=fr");
+        assert_eq!(QueryBuilder::new("http://host/p?x=1#top").param("y", "&").build(),
+                   "http://host/p?x=1&y=%26#top");
+        assert_eq!(QueryBuilder::new("/p?").param("y", "2").build(), "/p?y=2");
+    }
+}
@@ -0,0 +1,708 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// WebSocket support (RFC 6455). The opening handshake is a regular HTTP request, after
+// which the channel carries frames in both directions. The framing code is the same for
+// both ends of the connection, only the masking rules differ.
+
+use collections::{String, Vec};
+use core::str;
+
+use base64;
+use sha1::Sha1;
+use traits::{Channel, Entropy};
+use server::{Request, ResponseWriter};
+use {Client, HttpError, HttpHeader, HttpMethod};
+
+static GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Close status codes.
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_UNSUPPORTED_DATA: u16 = 1003;
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+pub const CLOSE_POLICY_VIOLATION: u16 = 1008;
+pub const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+pub const CLOSE_INTERNAL_ERROR: u16 = 1011;
+
+// Whether a code can be sent in a close frame. Some codes are reserved for reporting
+// locally, and 3000-4999 are for libraries and applications.
+pub fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(value: u8) -> Option<Opcode> {
+        match value {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xa => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(&self) -> u8 {
+        match *self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xa,
+        }
+    }
+
+    fn is_control(&self) -> bool {
+        self.as_u8() & 0x8 != 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WebSocketError {
+    // The server didn't switch to the WebSocket protocol.
+    HandshakeFailed,
+    // The peer doesn't follow the framing rules.
+    ProtocolError,
+    InvalidUtf8,
+    MessageTooLarge,
+    // The closing handshake happened, the connection can't be used anymore.
+    Closed,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Pong(Vec<u8>),
+    // The peer closed the connection, with an optional status code. The close frame has
+    // already been echoed back.
+    Close(Option<u16>),
+}
+
+// The Sec-WebSocket-Accept value matching a Sec-WebSocket-Key.
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    base64::encode(&sha1.digest())
+}
+
+fn find_header<'h>(headers: &'h [(HttpHeader, String)], name: &str) -> Option<&'h str> {
+    headers.iter()
+        .find(|header| header.0.name().eq_ignore_ascii_case(name))
+        .map(|header| header.1.as_str())
+}
+
+// Whether a comma separated header value contains a token, ignoring case.
+fn has_token(value: &str, token: &str) -> bool {
+    value.split(',').any(|item| item.trim().eq_ignore_ascii_case(token))
+}
+
+fn sec_header(name: &str) -> HttpHeader {
+    HttpHeader::Other(format!("Sec-WebSocket-{}:", name))
+}
+
+// Whether a request asks to switch to the WebSocket protocol.
+pub fn is_upgrade(request: &Request) -> bool {
+    let upgrade = request.header(&HttpHeader::Upgrade).unwrap_or("");
+    let connection = request.header(&HttpHeader::Connection).unwrap_or("");
+    // The key is a base64 encoded 16 bytes nonce.
+    let key = request.header(&sec_header("Key")).and_then(base64::decode);
+    request.method == HttpMethod::Get && upgrade.eq_ignore_ascii_case("websocket") &&
+    has_token(connection, "upgrade") && key.map(|key| key.len()) == Some(16)
+}
+
+struct Frame {
+    fin: bool,
+    opcode: Opcode,
+    payload: Vec<u8>,
+}
+
+pub struct WebSocket<'a, T: 'a> {
+    channel: &'a mut T,
+    // Clients mask their frames with random keys, servers don't mask.
+    entropy: Option<&'a mut dyn Entropy>,
+    max_message_size: usize,
+    // The type and data of a fragmented message being received.
+    partial: Option<(Opcode, Vec<u8>)>,
+    // We sent a close frame.
+    closing: bool,
+    // The closing handshake is done.
+    closed: bool,
+}
+
+impl<'a, T: Channel> WebSocket<'a, T> {
+    fn new(channel: &'a mut T, entropy: Option<&'a mut dyn Entropy>) -> Self {
+        WebSocket {
+            channel: channel,
+            entropy: entropy,
+            max_message_size: 16384,
+            partial: None,
+            closing: false,
+            closed: false,
+        }
+    }
+
+    // Performs the opening handshake for a ws:// or wss:// url. `headers` are added to the
+    // upgrade request, eg. for Sec-WebSocket-Protocol or authentication.
+    pub fn connect<'c>(client: &'a mut Client<'c, T>,
+                       url: &'c str,
+                       headers: &[(HttpHeader, &str)],
+                       entropy: &'a mut dyn Entropy)
+                       -> Result<Self, HttpError> {
+        let mut nonce = [0u8; 16];
+        entropy.fill(&mut nonce);
+        let key = base64::encode(&nonce);
+
+        let response = client.get(url)
+            .open()?
+            .headers(&[(HttpHeader::Upgrade, "websocket"),
+                       (HttpHeader::Connection, "Upgrade"),
+                       (sec_header("Key"), &key),
+                       (sec_header("Version"), "13")])?
+            .headers(headers)?
+            .response(|_| true)?;
+
+        let upgrade = find_header(&response.headers, "Upgrade").unwrap_or("");
+        let connection = find_header(&response.headers, "Connection").unwrap_or("");
+        let accept = find_header(&response.headers, "Sec-WebSocket-Accept").unwrap_or("");
+        let upgraded = response.status_code == 101 && upgrade.eq_ignore_ascii_case("websocket") &&
+                       has_token(connection, "upgrade") && accept == accept_key(&key);
+        if !upgraded {
+            return Err(HttpError::from(WebSocketError::HandshakeFailed));
+        }
+
+        Ok(WebSocket::new(response.body, Some(entropy)))
+    }
+
+    // Completes the opening handshake for a request from `is_upgrade`, sending the 101
+    // response. Other requests get an error response. `protocol` is the subprotocol picked
+    // from the Sec-WebSocket-Protocol request header, if any.
+    pub fn accept<'w>(request: &Request,
+                      response: &'a mut ResponseWriter<'w, T>,
+                      protocol: Option<&str>)
+                      -> Result<Self, HttpError> {
+        if !is_upgrade(request) {
+            response.status(400, "Bad Request")?.send(&[])?;
+            return Err(HttpError::from(WebSocketError::HandshakeFailed));
+        }
+        if request.header(&sec_header("Version")) != Some("13") {
+            response.status(426, "Upgrade Required")?
+                .header(sec_header("Version"), "13")?
+                .send(&[])?;
+            return Err(HttpError::from(WebSocketError::HandshakeFailed));
+        }
+
+        let key = request.header(&sec_header("Key")).unwrap();
+        response.status(101, "Switching Protocols")?
+            .header(HttpHeader::Upgrade, "websocket")?
+            .header(HttpHeader::Connection, "Upgrade")?
+            .header(sec_header("Accept"), &accept_key(key))?;
+        if let Some(protocol) = protocol {
+            response.header(sec_header("Protocol"), protocol)?;
+        }
+        response.body(&[])?;
+
+        Ok(WebSocket::new(response.channel(), None))
+    }
+
+    // Larger messages are rejected when receiving.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    pub fn send_text(&mut self, text: &str) -> Result<(), HttpError> {
+        self.send_frame(Opcode::Text, text.as_bytes(), true)
+    }
+
+    pub fn send_binary(&mut self, data: &[u8]) -> Result<(), HttpError> {
+        self.send_frame(Opcode::Binary, data, true)
+    }
+
+    pub fn ping(&mut self, data: &[u8]) -> Result<(), HttpError> {
+        self.send_frame(Opcode::Ping, data, true)
+    }
+
+    // Sends a single frame. Fragmented messages start with a Text or Binary frame, followed
+    // by Continuation frames, the last one having `fin` set.
+    pub fn send_frame(&mut self, opcode: Opcode, payload: &[u8], fin: bool) -> Result<(), HttpError> {
+        if self.closing || self.closed {
+            return Err(HttpError::from(WebSocketError::Closed));
+        }
+        if opcode.is_control() && (!fin || payload.len() > 125) {
+            return Err(HttpError::from(WebSocketError::ProtocolError));
+        }
+        self.write_frame(opcode, payload, fin)
+    }
+
+    fn write_frame(&mut self, opcode: Opcode, payload: &[u8], fin: bool) -> Result<(), HttpError> {
+        let mut header = [0u8; 14];
+        header[0] = opcode.as_u8() | if fin { 0x80 } else { 0 };
+        let mask = if self.entropy.is_some() { 0x80 } else { 0 };
+
+        let mut len = 2;
+        if payload.len() < 126 {
+            header[1] = mask | payload.len() as u8;
+        } else if payload.len() <= 0xffff {
+            header[1] = mask | 126;
+            header[2..4].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+            len = 4;
+        } else {
+            header[1] = mask | 127;
+            header[2..10].copy_from_slice(&(payload.len() as u64).to_be_bytes());
+            len = 10;
+        }
+
+        match self.entropy {
+            Some(ref mut entropy) => {
+                let mut key = [0u8; 4];
+                entropy.fill(&mut key);
+                header[len..len + 4].copy_from_slice(&key);
+                self.channel.send_all(&header[..len + 4])?;
+
+                let masked: Vec<u8> = payload.iter()
+                    .enumerate()
+                    .map(|(i, value)| value ^ key[i % 4])
+                    .collect();
+                self.channel.send_all(&masked)?;
+            }
+            None => {
+                self.channel.send_all(&header[..len])?;
+                self.channel.send_all(payload)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> Result<Frame, HttpError> {
+        let mut header = [0u8; 2];
+        self.channel.read_exact(&mut header)?;
+
+        // No extension is negotiated, so the reserved bits must be clear.
+        if header[0] & 0x70 != 0 {
+            return Err(HttpError::from(WebSocketError::ProtocolError));
+        }
+        let fin = header[0] & 0x80 != 0;
+        let opcode = Opcode::from_u8(header[0] & 0x0f)
+            .ok_or(HttpError::from(WebSocketError::ProtocolError))?;
+
+        // Frames from clients are masked, frames from servers are not.
+        let masked = header[1] & 0x80 != 0;
+        if masked != self.entropy.is_none() {
+            return Err(HttpError::from(WebSocketError::ProtocolError));
+        }
+
+        let mut len = (header[1] & 0x7f) as u64;
+        if len == 126 {
+            let mut value = [0u8; 2];
+            self.channel.read_exact(&mut value)?;
+            len = u16::from_be_bytes(value) as u64;
+        } else if len == 127 {
+            let mut value = [0u8; 8];
+            self.channel.read_exact(&mut value)?;
+            len = u64::from_be_bytes(value);
+        }
+
+        if opcode.is_control() && (!fin || len > 125) {
+            return Err(HttpError::from(WebSocketError::ProtocolError));
+        }
+        let received = self.partial.as_ref().map_or(0, |partial| partial.1.len());
+        if !opcode.is_control() && len > self.max_message_size.saturating_sub(received) as u64 {
+            return Err(HttpError::from(WebSocketError::MessageTooLarge));
+        }
+
+        let mut key = [0u8; 4];
+        if masked {
+            self.channel.read_exact(&mut key)?;
+        }
+        let mut payload = vec![0u8; len as usize];
+        self.channel.read_exact(&mut payload)?;
+        if masked {
+            for (i, value) in payload.iter_mut().enumerate() {
+                *value ^= key[i % 4];
+            }
+        }
+
+        Ok(Frame {
+            fin: fin,
+            opcode: opcode,
+            payload: payload,
+        })
+    }
+
+    // Waits for the next message, reassembling fragmented ones. Pings are answered
+    // automatically. If the peer breaks the protocol, the connection is closed with the
+    // matching status code.
+    pub fn recv(&mut self) -> Result<Message, HttpError> {
+        if self.closed {
+            return Err(HttpError::from(WebSocketError::Closed));
+        }
+
+        let res = self.read_message();
+        let code = match res {
+            Err(HttpError::WebSocket(WebSocketError::ProtocolError)) => CLOSE_PROTOCOL_ERROR,
+            Err(HttpError::WebSocket(WebSocketError::InvalidUtf8)) => CLOSE_INVALID_DATA,
+            Err(HttpError::WebSocket(WebSocketError::MessageTooLarge)) => CLOSE_MESSAGE_TOO_BIG,
+            _ => return res,
+        };
+        if !self.closing {
+            // We are failing the connection anyway, the error we report is the first one.
+            let _ = self.write_frame(Opcode::Close, &code.to_be_bytes(), true);
+        }
+        self.closed = true;
+        res
+    }
+
+    fn read_message(&mut self) -> Result<Message, HttpError> {
+        loop {
+            let frame = self.read_frame()?;
+            match frame.opcode {
+                Opcode::Ping => {
+                    if !self.closing {
+                        self.write_frame(Opcode::Pong, &frame.payload, true)?;
+                    }
+                    continue;
+                }
+                Opcode::Pong => return Ok(Message::Pong(frame.payload)),
+                Opcode::Close => {
+                    let code = match frame.payload.len() {
+                        0 => None,
+                        1 => return Err(HttpError::from(WebSocketError::ProtocolError)),
+                        _ => Some(u16::from_be_bytes([frame.payload[0], frame.payload[1]])),
+                    };
+                    if code.map(is_valid_close_code) == Some(false) {
+                        return Err(HttpError::from(WebSocketError::ProtocolError));
+                    }
+                    if code.is_some() && str::from_utf8(&frame.payload[2..]).is_err() {
+                        return Err(HttpError::from(WebSocketError::InvalidUtf8));
+                    }
+                    if !self.closing {
+                        let len = if code.is_some() { 2 } else { 0 };
+                        self.write_frame(Opcode::Close, &frame.payload[..len], true)?;
+                    }
+                    self.closed = true;
+                    return Ok(Message::Close(code));
+                }
+                Opcode::Continuation => {
+                    match self.partial {
+                        Some(ref mut partial) => partial.1.extend_from_slice(&frame.payload),
+                        None => return Err(HttpError::from(WebSocketError::ProtocolError)),
+                    }
+                }
+                Opcode::Text | Opcode::Binary => {
+                    if self.partial.is_some() {
+                        return Err(HttpError::from(WebSocketError::ProtocolError));
+                    }
+                    self.partial = Some((frame.opcode, frame.payload));
+                }
+            }
+
+            if frame.fin {
+                let (opcode, data) = self.partial.take().unwrap();
+                if opcode == Opcode::Binary {
+                    return Ok(Message::Binary(data));
+                }
+                return String::from_utf8(data)
+                    .map(Message::Text)
+                    .map_err(|_| HttpError::from(WebSocketError::InvalidUtf8));
+            }
+        }
+    }
+
+    // Starts the closing handshake and waits for the peer to answer, dropping the messages
+    // still in flight.
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<(), HttpError> {
+        if self.closing || self.closed {
+            return Err(HttpError::from(WebSocketError::Closed));
+        }
+        if reason.len() > 123 {
+            return Err(HttpError::from(WebSocketError::ProtocolError));
+        }
+
+        let mut payload = Vec::with_capacity(2 + reason.len());
+        payload.extend_from_slice(&code.to_be_bytes());
+        payload.extend_from_slice(reason.as_bytes());
+        self.write_frame(Opcode::Close, &payload, true)?;
+        self.closing = true;
+
+        loop {
+            if let Message::Close(_) = self.recv()? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::str;
+    use server::{Handler, Server};
+    use traits::{ChannelError, MemoryChannel};
+
+    // Cycles over the nonce from the RFC example.
+    struct SampleNonce(usize);
+
+    impl Entropy for SampleNonce {
+        fn fill(&mut self, data: &mut [u8]) {
+            for value in data.iter_mut() {
+                *value = b"the sample nonce"[self.0 % 16];
+                self.0 += 1;
+            }
+        }
+    }
+
+    // A peer that closed the connection, reads return no data.
+    struct Eof;
+
+    impl Channel for Eof {
+        fn open(&mut self, _: &str, _: u16, _: bool) -> Result<(), ChannelError> {
+            Ok(())
+        }
+
+        fn send(&mut self, _: &[u8], len: usize) -> Result<usize, ChannelError> {
+            Ok(len)
+        }
+
+        fn recv(&mut self, _: &mut [u8], _: usize) -> Result<usize, ChannelError> {
+            Ok(0)
+        }
+    }
+
+    static HANDSHAKE: &'static str = "HTTP/1.1 101 Switching Protocols\r\nUpgrade: \
+                                      websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: \
+                                      s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+
+    // Accepts the upgrade and echoes a message back.
+    struct Echo;
+
+    impl<T: Channel> Handler<T> for Echo {
+        fn handle(&mut self,
+                  request: &Request,
+                  response: &mut ResponseWriter<T>)
+                  -> Result<(), HttpError> {
+            let protocol = request.header(&sec_header("Protocol"));
+            let mut socket = WebSocket::accept(request, response, protocol)?;
+            match socket.recv()? {
+                Message::Text(text) => socket.send_text(&text)?,
+                message => panic!("Unexpected message {:?}", message),
+            }
+            assert_eq!(socket.recv()?, Message::Close(Some(CLOSE_GOING_AWAY)));
+            Ok(())
+        }
+    }
+
+    // Frames as sent by a client.
+    fn client_frames<F>(write: F) -> Vec<u8>
+        where F: FnOnce(&mut WebSocket<MemoryChannel>)
+    {
+        let mut channel = MemoryChannel::new(b"");
+        let mut nonce = SampleNonce(0);
+        write(&mut WebSocket::new(&mut channel, Some(&mut nonce)));
+        channel.sent().to_vec()
+    }
+
+    fn serve_echo(request: &str, frames: &[u8]) -> (Result<(), HttpError>, Vec<u8>) {
+        let mut input = request.as_bytes().to_vec();
+        input.extend_from_slice(frames);
+        let mut channel = MemoryChannel::new(&input);
+        let res = Server::new().serve(&mut channel, &mut Echo);
+        (res, channel.sent().to_vec())
+    }
+
+    #[test]
+    fn test_accept_key() {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+                   "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_websocket_client() {
+        let mut input = Vec::new();
+        input.extend_from_slice(HANDSHAKE.as_bytes());
+        // A single frame message.
+        input.extend_from_slice(b"\x81\x05Hello");
+        // A fragmented message, with a ping in the middle.
+        input.extend_from_slice(b"\x01\x03Hel\x89\x02hi\x80\x02lo");
+        // A binary message with a 16 bits length.
+        input.extend_from_slice(b"\x82\x7e\x01\x00");
+        input.extend_from_slice(&[7u8; 256]);
+        input.extend_from_slice(b"\x8a\x00");
+        // The answer to our close frame, with a message in flight.
+        input.extend_from_slice(b"\x81\x01x\x88\x02\x03\xe8");
+
+        let mut channel = MemoryChannel::new(&input);
+        let mut nonce = SampleNonce(0);
+        {
+            let mut client = Client::new(&mut channel);
+            let mut socket = WebSocket::connect(&mut client,
+                                                "ws://localhost/updates",
+                                                &[(HttpHeader::Other(String::from("Sec-WebSocket-Protocol:")),
+                                                   "v1")],
+                                                &mut nonce)
+                .unwrap();
+
+            assert_eq!(socket.recv().unwrap(), Message::Text(String::from("Hello")));
+            assert_eq!(socket.recv().unwrap(), Message::Text(String::from("Hello")));
+            assert_eq!(socket.recv().unwrap(), Message::Binary(vec![7u8; 256]));
+            assert_eq!(socket.recv().unwrap(), Message::Pong(Vec::new()));
+
+            socket.send_text("Hi").unwrap();
+            socket.send_frame(Opcode::Binary, &[1, 2], false).unwrap();
+            socket.send_frame(Opcode::Continuation, &[3], true).unwrap();
+            socket.close(1000, "bye").unwrap();
+            assert_eq!(socket.send_text("late").err().unwrap(),
+                       HttpError::WebSocket(WebSocketError::Closed));
+        }
+
+        let sent = channel.sent();
+        let request = "GET /updates HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: \
+                       Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: \
+                       13\r\nSec-WebSocket-Protocol: v1\r\n\r\n";
+        assert_eq!(str::from_utf8(&sent[..request.len()]).unwrap(), request);
+
+        // Read our frames back, from the server side.
+        let mut frames = MemoryChannel::new(&sent[request.len()..]);
+        let mut server = WebSocket::new(&mut frames, None);
+        assert_eq!(server.recv().unwrap(), Message::Pong(b"hi".to_vec()));
+        assert_eq!(server.recv().unwrap(), Message::Text(String::from("Hi")));
+        assert_eq!(server.recv().unwrap(), Message::Binary(vec![1, 2, 3]));
+        assert_eq!(server.recv().unwrap(), Message::Close(Some(1000)));
+    }
+
+    #[test]
+    fn test_websocket_errors() {
+        let mut nonce = SampleNonce(0);
+
+        // Wrong accept key.
+        let mut channel = MemoryChannel::new(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: \
+                                               websocket\r\nConnection: \
+                                               Upgrade\r\nSec-WebSocket-Accept: x\r\n\r\n");
+        let mut client = Client::new(&mut channel);
+        assert_eq!(WebSocket::connect(&mut client, "ws://localhost/", &[], &mut nonce)
+                       .err()
+                       .unwrap(),
+                   HttpError::WebSocket(WebSocketError::HandshakeFailed));
+
+        let frames: [&[u8]; 5] = [// Masked frame from the server.
+                                  b"\x81\x81abcdx",
+                                  // Continuation without a message.
+                                  b"\x80\x01x",
+                                  // Fragmented control frame.
+                                  b"\x09\x00",
+                                  // Reserved bit.
+                                  b"\xc1\x01x",
+                                  // Invalid utf-8.
+                                  b"\x81\x01\xff"];
+        let errors = [WebSocketError::ProtocolError,
+                      WebSocketError::ProtocolError,
+                      WebSocketError::ProtocolError,
+                      WebSocketError::ProtocolError,
+                      WebSocketError::InvalidUtf8];
+        for (frame, error) in frames.iter().zip(errors.iter()) {
+            let mut channel = MemoryChannel::new(frame);
+            let mut socket = WebSocket::new(&mut channel, Some(&mut nonce));
+            assert_eq!(socket.recv().err().unwrap(), HttpError::WebSocket(*error));
+        }
+
+        let mut channel = MemoryChannel::new(b"\x82\x7e\x01\x00");
+        let mut socket = WebSocket::new(&mut channel, Some(&mut nonce));
+        socket.set_max_message_size(255);
+        assert_eq!(socket.recv().err().unwrap(),
+                   HttpError::WebSocket(WebSocketError::MessageTooLarge));
+
+        // Lowering the limit below the size of the message received so far.
+        let mut channel = MemoryChannel::new(b"\x02\x02ab\x8a\x00\x80\x01c");
+        let mut socket = WebSocket::new(&mut channel, Some(&mut nonce));
+        assert_eq!(socket.recv().unwrap(), Message::Pong(Vec::new()));
+        socket.set_max_message_size(1);
+        assert_eq!(socket.recv().err().unwrap(),
+                   HttpError::WebSocket(WebSocketError::MessageTooLarge));
+
+        // The connection closed in the middle of a frame.
+        let mut channel = Eof;
+        let mut socket = WebSocket::new(&mut channel, Some(&mut nonce));
+        assert_eq!(socket.recv().err().unwrap(),
+                   HttpError::ChannelError(ChannelError::EndOfStream));
+    }
+
+    #[test]
+    fn test_websocket_server() {
+        let frames = client_frames(|socket| {
+            socket.send_text("help").unwrap();
+            socket.send_frame(Opcode::Close, &CLOSE_GOING_AWAY.to_be_bytes(), true).unwrap();
+        });
+        let (res, sent) = serve_echo("GET /console HTTP/1.1\r\nHost: device\r\nUpgrade: \
+                                      websocket\r\nConnection: keep-alive, \
+                                      Upgrade\r\nSec-WebSocket-Key: \
+                                      dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: \
+                                      13\r\nSec-WebSocket-Protocol: chat\r\n\r\n",
+                                     &frames);
+        res.unwrap();
+        let mut expected = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: \
+                             Upgrade\r\nSec-WebSocket-Accept: \
+                             s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\nSec-WebSocket-Protocol: chat\r\n\r\n"
+            .to_vec();
+        expected.extend_from_slice(b"\x81\x04help\x88\x02\x03\xe9");
+        assert_eq!(sent, expected);
+    }
+
+    #[test]
+    fn test_websocket_server_errors() {
+        let (res, sent) = serve_echo("GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: \
+                                      Upgrade\r\nSec-WebSocket-Key: \
+                                      dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+                                     &[]);
+        assert_eq!(res.err().unwrap(),
+                   HttpError::WebSocket(WebSocketError::HandshakeFailed));
+        assert!(sent.starts_with(b"HTTP/1.1 426 Upgrade Required\r\nSec-WebSocket-Version: 13\r\n"));
+
+        let (res, sent) = serve_echo("GET / HTTP/1.1\r\nSec-WebSocket-Key: \
+                                      dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: \
+                                      13\r\n\r\n",
+                                     &[]);
+        assert_eq!(res.err().unwrap(),
+                   HttpError::WebSocket(WebSocketError::HandshakeFailed));
+        assert!(sent.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+
+        // Errors are reported to the peer with a close frame.
+        let cases: [(Vec<u8>, WebSocketError, u16); 3] =
+            [(client_frames(|socket| socket.send_text("too long!").unwrap()),
+              WebSocketError::MessageTooLarge,
+              CLOSE_MESSAGE_TOO_BIG),
+             (client_frames(|socket| socket.send_frame(Opcode::Text, &[0xff], true).unwrap()),
+              WebSocketError::InvalidUtf8,
+              CLOSE_INVALID_DATA),
+             (client_frames(|socket| socket.send_frame(Opcode::Close, &[3, 231], true).unwrap()),
+              WebSocketError::ProtocolError,
+              CLOSE_PROTOCOL_ERROR)];
+        for case in cases.iter() {
+            let mut channel = MemoryChannel::new(&case.0);
+            {
+                let mut socket = WebSocket::new(&mut channel, None);
+                socket.set_max_message_size(8);
+                assert_eq!(socket.recv().err().unwrap(), HttpError::WebSocket(case.1));
+                assert_eq!(socket.recv().err().unwrap(),
+                           HttpError::WebSocket(WebSocketError::Closed));
+            }
+            let mut close = vec![0x88, 2];
+            close.extend_from_slice(&case.2.to_be_bytes());
+            assert_eq!(channel.sent(), close.as_slice());
+        }
+    }
+}